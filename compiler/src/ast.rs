@@ -1,14 +1,30 @@
 use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
 use serde::{Deserialize, Serialize};
-use crate::scratch_schema::Costume;
+use crate::diagnostic::{Diagnostic, Location};
+use crate::names::Names;
+use crate::scratch_schema::{Costume, TwConfig};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Project {
     pub targets: Vec<Sprite>,
+    /// Unique rust identifiers.
     pub var_names: Vec<String>,
+    /// The original names for debug display and matching computed strings.
+    pub scratch_names: Vec<String>,
+    pub names: Names,
     pub expected_types: Vec<Option<SType>>,
     pub triggers_by_name: HashMap<String, VarId>,
     pub any_async: bool,
+    /// Globals shared between every running copy of the program.
+    pub cloud_vars: Vec<VarId>,
+    /// From TurboWarp's settings comment on the stage. Scratch's defaults if there isn't one.
+    pub config: TwConfig,
+    /// Warnings from parsing. Errors are returned by try_from instead.
+    pub diagnostics: Vec<Diagnostic>,
+    /// Where expect_type is being called from, for the warning when a variable changes type.
+    #[serde(skip)]
+    pub at: Location,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -18,6 +34,8 @@ pub struct Sprite {
     pub fields: Vec<VarId>,
     pub field_defaults: Vec<Option<Expr>>,
     pub name: String,
+    /// The original name for diagnostics.
+    pub scratch_name: String,
     pub is_stage: bool,
     pub is_singleton: bool,
     pub costumes: Vec<Costume>,
@@ -29,17 +47,25 @@ pub struct Sprite {
 pub struct Func {
     pub start: Trigger,
     pub body: Vec<Stmt>,
-    pub needs_async: bool
+    pub needs_async: bool,
+    /// Unique rust identifier for the struct backend::rust runs it with.
+    pub machine: String,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Proc {
     pub name: String,
+    pub scratch_name: String,
     pub body: Vec<Stmt>,
     pub args: Vec<VarId>,
-    pub needs_async: bool
+    pub needs_async: bool,
+    /// Unique rust identifier for the struct backend::rust runs it with if it's async.
+    pub machine: String,
 }
 
+/// The scratch block a node came from so backends can say where something they can't do is.
+pub type BlockId = String;
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Node {
     pub op: Stmt
@@ -53,11 +79,18 @@ pub enum Stmt {
     RepeatUntil(Expr, Vec<Stmt>),
     IfElse(Expr, Vec<Stmt>, Vec<Stmt>),
     // WaitUntil(Expr),
+    /// Returns from the current custom block, or ends the script if not in one.
     StopScript,
+    /// Stop all.
     Exit,
+    /// Stop other scripts in sprite.
+    StopOtherScripts,
     RepeatTimesCapture(Expr, Vec<Stmt>, VarId, Scope),
+    /// Ends each loop body outside a run without screen refresh block. Only yields in RunMode::Scratch.
+    /// Makes the procedure it's in async so the loop can yield there too.
+    LoopYield,
 
-    CloneMyself,
+    CloneMyself(BlockId),
     WaitSeconds(Expr),
     AskAndWait(Expr),
 
@@ -70,7 +103,7 @@ pub enum Stmt {
     ListRemoveIndex(Scope, VarId, Expr),
 
     // Other
-    BuiltinRuntimeCall(String, Vec<Expr>),
+    BuiltinRuntimeCall(String, Vec<Expr>, BlockId),
     CallCustom(String, Vec<Expr>),  // TODO: func name should be a VarId
     BroadcastWait(Expr),
     UnknownOpcode(String),
@@ -96,10 +129,21 @@ pub enum Expr {
     Empty,
     IsNum(Box<Expr>),
 
-    BuiltinRuntimeGet(String),
-    Literal(String),  // TODO: parse it in parser
+    BuiltinRuntimeGet(String, BlockId),
+    Literal(Lit),
     UnknownExpr(String),
     ListLiteral(Vec<f64>),
+    /// A constant broadcast name resolved by opt. None if no script receives it.
+    Message(Option<VarId>),
+}
+
+/// A constant, parsed once from the string scratch stores it as.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Lit {
+    Num(f64),
+    /// Anything that isn't exactly how a number prints, including numbers like "007" or "1.50" (see Lit::num).
+    Str(String),
+    Bool(bool),
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
@@ -137,14 +181,125 @@ pub enum UnOp {
 
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq, Hash, Copy)]
 pub enum SType {
-    // Integer,
+    /// A Number that is only ever set to whole numbers that stay in range (see parse::is_int). Emitted as an i64.
+    /// Only assigned by infer::run_infer_integers after the rest of inference is done.
+    Integer,
     Number,
     Bool,
     Str,
+    /// A list that nothing has been written to (yet). Emitted as a List<Poly>.
+    ListUnknown,
+    ListNumber,
+    ListStr,
     ListPoly,
     Poly
 }
 
+impl Stmt {
+    /// The blocks nested inside this one. Passes that only care about some statements use this to still visit everything.
+    pub fn bodies(&self) -> Vec<&[Stmt]> {
+        match self {
+            Stmt::RepeatTimes(_, body) |
+            Stmt::If(_, body) |
+            Stmt::RepeatUntil(_, body) |
+            Stmt::RepeatTimesCapture(_, body, _, _) => vec![body],
+            Stmt::IfElse(_, body, body2) => vec![body, body2],
+            Stmt::StopScript | Stmt::Exit | Stmt::StopOtherScripts | Stmt::LoopYield | Stmt::CloneMyself(_) |
+            Stmt::WaitSeconds(_) | Stmt::AskAndWait(_) | Stmt::SetField(_, _) | Stmt::SetGlobal(_, _) |
+            Stmt::ListSet(_, _, _, _) | Stmt::ListPush(_, _, _) | Stmt::ListClear(_, _) | Stmt::ListRemoveIndex(_, _, _) |
+            Stmt::BuiltinRuntimeCall(_, _, _) | Stmt::CallCustom(_, _) | Stmt::BroadcastWait(_) | Stmt::UnknownOpcode(_) | Stmt::Empty => vec![],
+        }
+    }
+}
+
+impl Expr {
+    /// Scratch stores all constants as strings.
+    pub fn constant(s: &str) -> Expr {
+        if s.is_empty() {
+            Expr::Empty
+        } else {
+            Expr::Literal(Lit::parse(s))
+        }
+    }
+}
+
+impl Lit {
+    /// Only a Num if it prints back as the same text, otherwise say "007" would print 7.
+    /// Never a Bool. Typed text "true" is a string ("true" + 1 is 1) and scratch has no boolean literals.
+    pub fn parse(s: &str) -> Lit {
+        match s.parse::<f64>() {
+            Ok(v) if v.to_string() == s => Lit::Num(v),
+            _ => Lit::Str(s.to_string()),
+        }
+    }
+
+    /// The value where a number is expected. Matches Poly::as_num.
+    pub fn num(&self) -> f64 {
+        match self {
+            Lit::Num(v) => *v,
+            Lit::Str(s) => s.parse().unwrap_or(0.0),
+            Lit::Bool(_) => 0.0,
+        }
+    }
+
+    pub fn ty(&self) -> SType {
+        match self {
+            Lit::Num(_) => SType::Number,
+            Lit::Str(_) => SType::Str,
+            Lit::Bool(_) => SType::Bool,
+        }
+    }
+}
+
+/// Matches what the runtime does when converting to a Str.
+impl Display for Lit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Lit::Num(v) => write!(f, "{v}"),
+            Lit::Str(s) => f.write_str(s),
+            Lit::Bool(b) => write!(f, "{b}"),
+        }
+    }
+}
+
+impl SType {
+    pub fn is_list(self) -> bool {
+        matches!(self, SType::ListUnknown | SType::ListNumber | SType::ListStr | SType::ListPoly)
+    }
+
+    /// The type of a list that holds values of type self.
+    pub fn list_of(self) -> SType {
+        match self {
+            SType::Integer | SType::Number => SType::ListNumber,
+            SType::Str => SType::ListStr,
+            SType::Bool | SType::Poly => SType::ListPoly,  // TODO: does scratch store bools in lists as strings?
+            // Lists are not first class values, a list written into a list is just some value.
+            _ => SType::ListPoly,
+        }
+    }
+
+    /// The type of a value read from a list of type self. None if nothing has been written to it yet.
+    pub fn list_item(self) -> Option<SType> {
+        match self {
+            SType::ListUnknown => None,
+            SType::ListNumber => Some(SType::Number),
+            SType::ListStr => Some(SType::Str),
+            SType::ListPoly => Some(SType::Poly),
+            _ => panic!("Expected list found {self:?}"),
+        }
+    }
+
+    /// Lists only ever get less specific: Unknown -> Number/Str -> Poly.
+    pub fn join_list(self, other: SType) -> SType {
+        assert!(self.is_list() && other.is_list(), "Lists are not first class values, can't join {self:?} with {other:?}");
+        match (self, other) {
+            (SType::ListUnknown, t) | (t, SType::ListUnknown) => t,
+            (a, b) if a == b => a,
+            _ => SType::ListPoly,
+        }
+    }
+}
+
 // #[derive(Serialize, Deserialize, Debug, Clone)]
 // pub enum RotStyle {
 //     LeftRight,
@@ -164,7 +319,6 @@ pub enum SType {
 pub enum Trigger {
     FlagClicked,
     SpriteClicked,
-    /// Corresponding name is NOT a safe_str
     Message(VarId),
     // KeyPressed(KeyId),
     // ThisSpriteClicked,
@@ -0,0 +1,563 @@
+//! Runs a Project directly from the AST on the runtime's World instead of generating a crate.
+//! Much slower than backend::rust but there's no waiting for rustc and no toolchain needed.
+//! Every value is a Poly but writes are coerced to the inferred type so results match the compiled code.
+//! Only the console and trace backends can run it (cli --interpret and the differential tests). There's no graphical backend
+//! and it isn't exported by wasm_interface so it can't run projects in the browser yet.
+
+use std::any::Any;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::num::NonZeroU16;
+use runtime::backend::{console, trace};
+use runtime::{dyn_rand, fut_a, state, Config, FrameCtx, FutRes, IoAction, List, Machine, Poly, RenderBackend, ScratchAsset, ScratchProgram, Sprite, Str, Trigger, VarBorrow, VarBorrowMut};
+use crate::ast::{self, BinOp, Expr, Lit, Project, Scope, SType, Stmt, UnOp, VarId};
+
+type Ctx<'a, 'b, R> = FrameCtx<'a, 'b, Program, R>;
+
+struct Loaded {
+    project: Project,
+    costumes: Vec<&'static [u8]>,
+    /// See ScratchProgram::costume_names.
+    costume_names: &'static [(usize, &'static [&'static str])],
+    /// The scratch names of each target's fields.
+    var_names: Vec<&'static [&'static str]>,
+    /// See ScratchProgram::receivers.
+    receivers: HashMap<ast::Trigger, &'static [(usize, usize)]>,
+}
+
+thread_local! {
+    static LOADED: Cell<Option<&'static Loaded>> = const { Cell::new(None) };
+}
+
+/// Set the project that Program runs. Costumes are image bytes in the same order backend::rust includes them.
+/// Everything is leaked because the ScratchProgram functions are static.
+pub fn load(project: Project, costumes: Vec<Vec<u8>>) {
+    let mut costume_names = vec![];
+    let mut first = 0;
+    for target in &project.targets {
+        let names: Vec<&'static str> = target.costumes.iter().map(|c| &*c.name.clone().leak()).collect();
+        if !target.is_stage {
+            costume_names.push((first, &*names.leak()));
+        }
+        first += target.costumes.len();
+    }
+    let costume_names = costume_names.leak();
+    let costumes = costumes.into_iter().map(|bytes| &*bytes.leak()).collect();
+    let var_names = project.targets.iter().map(|t| {
+        let names: Vec<&'static str> = t.fields.iter().map(|v| &*project.scratch_names[v.0].clone().leak()).collect();
+        &*names.leak()
+    }).collect();
+    let mut listeners: HashMap<ast::Trigger, Vec<(usize, usize)>> = HashMap::new();
+    let sprites: Vec<_> = project.targets.iter().filter(|t| !t.is_stage).collect();
+    for (i, target) in sprites.iter().enumerate().rev() {
+        let mut hats: HashMap<ast::Trigger, usize> = HashMap::new();
+        for func in &target.scripts {
+            let hat = hats.entry(func.start).or_default();
+            listeners.entry(func.start).or_default().push((i, *hat));
+            *hat += 1;
+        }
+    }
+    let receivers = listeners.into_iter().map(|(trigger, scripts)| (trigger, &*scripts.leak())).collect();
+    LOADED.set(Some(Box::leak(Box::new(Loaded { project, costumes, costume_names, var_names, receivers }))));
+}
+
+fn loaded() -> &'static Loaded {
+    LOADED.get().expect("interp::load must be called before running the Program")
+}
+
+fn project() -> &'static Project {
+    &loaded().project
+}
+
+/// Whichever project was passed to load.
+#[derive(Debug)]
+pub struct Program;
+
+// Every runtime backend requires S: ScratchProgram<Self> so a blanket impl would be circular.
+macro_rules! impl_program {
+    ($($backend:ty),*) => {$(
+        impl ScratchProgram<$backend> for Program {
+            type Msg = VarId;
+            type Globals = Instance;
+
+            fn create_initial_state() -> (Instance, Vec<Box<dyn Sprite<Program, $backend>>>) {
+                // Same as backend::rust, the stage only holds the globals so its scripts don't run.
+                let mut stage = None;
+                let mut sprites: Vec<Box<dyn Sprite<Program, $backend>>> = vec![];
+                for (i, target) in project().targets.iter().enumerate() {
+                    if target.is_stage {
+                        stage = Some(Instance::new(i));
+                    } else {
+                        sprites.push(Box::new(Instance::new(i)));
+                    }
+                }
+                (stage.expect("Project has no stage"), sprites)
+            }
+
+            fn get_costumes() -> Vec<ScratchAsset> {
+                loaded().costumes.iter().map(|bytes| ScratchAsset::Embed(bytes)).collect()
+            }
+
+            fn costume_names() -> &'static [(usize, &'static [&'static str])] {
+                loaded().costume_names
+            }
+
+            fn get_credits() -> &'static str {
+                r#"This program is interpreted from a Scratch project using github.com/LukeGrahamLandry/hctarcs
+All projects shared on the Scratch website are covered by the Creative Commons Attribution Share-Alike license.
+Scratch is a project of the Scratch Foundation, in collaboration with the Lifelong Kindergarten Group at the MIT Media Lab. It is available for free at https://scratch.mit.edu"#
+            }
+
+            fn receivers(trigger: Trigger<VarId>) -> &'static [(usize, usize)] {
+                let trigger = match trigger {
+                    Trigger::FlagClicked => ast::Trigger::FlagClicked,
+                    Trigger::SpriteClicked => ast::Trigger::SpriteClicked,
+                    Trigger::Message(msg) => ast::Trigger::Message(msg),
+                    Trigger::UiClearPen => return &[],
+                };
+                loaded().receivers.get(&trigger).copied().unwrap_or(&[])
+            }
+
+            fn config() -> Config {
+                let c = &project().config;
+                Config {
+                    framerate: c.framerate,
+                    turbo: c.turbo,
+                    interpolation: c.interpolation,
+                    hq_pen: c.hq,
+                    fencing: c.runtimeOptions.fencing,
+                    max_clones: c.runtimeOptions.maxClones.map_or(usize::MAX, |n| n as usize),
+                    misc_limits: c.runtimeOptions.miscLimits,
+                    width: c.width,
+                    height: c.height,
+                }
+            }
+        }
+    )*};
+}
+
+impl_program!(Console, trace::BackendImpl<Program>);
+
+/// The variables of one sprite. The stage's instance holds the globals.
+#[derive(Debug, Clone)]
+pub struct Instance {
+    target: usize,
+    vars: HashMap<VarId, Value>,
+}
+
+#[derive(Debug, Clone)]
+enum Value {
+    Scalar(Poly),
+    List(List<Poly>),
+}
+
+impl Instance {
+    fn new(target: usize) -> Self {
+        let sprite = &project().targets[target];
+        let vars = sprite.fields.iter().zip(sprite.field_defaults.iter()).map(|(v, default)| {
+            let value = match default {
+                Some(Expr::ListLiteral(items)) => Value::List(items.clone().into()),
+                Some(Expr::Literal(lit)) => store(*v, literal(lit)),
+                _ if ty(*v).is_some_and(SType::is_list) => Value::List(List::default()),
+                _ => store(*v, Poly::Empty),
+            };
+            (*v, value)
+        }).collect();
+        Instance { target, vars }
+    }
+}
+
+impl<R: RenderBackend<Program>> Sprite<Program, R> for Instance where Program: ScratchProgram<R, Msg = VarId, Globals = Instance> {
+    fn receive_async(&self, msg: Trigger<VarId>, hat: usize) -> IoAction<Program, R> {
+        project().targets[self.target].scripts.iter()
+            .filter(|func| match (func.start, msg) {
+                (ast::Trigger::FlagClicked, Trigger::FlagClicked) | (ast::Trigger::SpriteClicked, Trigger::SpriteClicked) => true,
+                (ast::Trigger::Message(a), Trigger::Message(b)) => a == b,
+                _ => false,
+            })
+            .nth(hat)
+            .map_or(IoAction::None, |func| Thread::start(&func.body))
+    }
+
+    fn clone_boxed(&self) -> Box<dyn Sprite<Program, R>> {
+        Box::new(self.clone())
+    }
+
+    fn get_var_names(&self) -> &'static [&'static str] {
+        loaded().var_names[self.target]
+    }
+
+    fn var(&self, i: usize) -> VarBorrow<'_> {
+        match self.vars.get(&project().targets[self.target].fields[i]) {
+            Some(Value::Scalar(value)) => VarBorrow::Poly(value),
+            Some(Value::List(list)) => VarBorrow::List(list),
+            None => VarBorrow::Fail,
+        }
+    }
+
+    fn var_mut(&mut self, i: usize) -> VarBorrowMut<'_> {
+        match self.vars.get_mut(&project().targets[self.target].fields[i]) {
+            Some(Value::Scalar(value)) => VarBorrowMut::Poly(value),
+            Some(Value::List(list)) => VarBorrowMut::List(list),
+            None => VarBorrowMut::Fail,
+        }
+    }
+}
+
+/// Where a running script is up to.
+/// Loops and procedure calls push frames instead of recursing so it can stop at any await point and resume later.
+struct Thread {
+    stack: Vec<Frame>,
+}
+
+enum Frame {
+    Block { body: &'static [Stmt], pc: usize },
+    Times { body: &'static [Stmt], i: usize, end: usize, capture: Option<(VarId, Scope)> },
+    Until { cond: &'static Expr, body: &'static [Stmt] },
+    /// The arguments of a procedure. StopScript returns to the frame under this one.
+    /// Loops in a procedure that is compiled sync don't yield.
+    Call { args: HashMap<VarId, Poly>, needs_async: bool },
+}
+
+impl Thread {
+    fn start<R: RenderBackend<Program>>(body: &'static [Stmt]) -> IoAction<Program, R> where Program: ScratchProgram<R, Msg = VarId, Globals = Instance> {
+        fut_a("interp", Thread { stack: vec![Frame::Block { body, pc: 0 }] })
+    }
+
+    /// Runs until the script has to wait on the world. None when it's finished.
+    fn run<R: RenderBackend<Program>>(&mut self, ctx: &mut Ctx<R>, this: &mut Instance) -> Option<IoAction<Program, R>> where Program: ScratchProgram<R, Msg = VarId, Globals = Instance> {
+        loop {
+            match self.stack.last_mut()? {
+                Frame::Block { body, pc } => {
+                    let Some(stmt) = body.get(*pc) else {
+                        self.stack.pop();
+                        continue;
+                    };
+                    *pc += 1;
+                    if let Some(action) = self.exec(stmt, ctx, this) {
+                        return Some(action);
+                    }
+                }
+                Frame::Times { body, i, end, capture } => {
+                    if *i >= *end {
+                        self.stack.pop();
+                        continue;
+                    }
+                    *i += 1;
+                    let (body, i, capture) = (*body, *i, *capture);
+                    if let Some((v, scope)) = capture {
+                        self.set(scope, v, Poly::Num(i as f64), ctx, this);
+                    }
+                    self.stack.push(Frame::Block { body, pc: 0 });
+                }
+                Frame::Until { cond, body } => {
+                    let (cond, body) = (*cond, *body);
+                    if self.eval(cond, ctx, this).as_bool() {
+                        self.stack.pop();
+                    } else {
+                        self.stack.push(Frame::Block { body, pc: 0 });
+                    }
+                }
+                Frame::Call { .. } => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+
+    fn exec<R: RenderBackend<Program>>(&mut self, stmt: &'static Stmt, ctx: &mut Ctx<R>, this: &mut Instance) -> Option<IoAction<Program, R>> where Program: ScratchProgram<R, Msg = VarId, Globals = Instance> {
+        match stmt {
+            Stmt::RepeatTimes(times, body) => {
+                let end = self.eval(times, ctx, this).as_num() as usize;
+                self.stack.push(Frame::Times { body, i: 0, end, capture: None });
+            }
+            Stmt::RepeatTimesCapture(times, body, v, s) => {
+                let end = self.eval(times, ctx, this).as_num() as usize;
+                self.stack.push(Frame::Times { body, i: 0, end, capture: Some((*v, *s)) });
+            }
+            Stmt::If(cond, body) => {
+                if self.eval(cond, ctx, this).as_bool() {
+                    self.stack.push(Frame::Block { body, pc: 0 });
+                }
+            }
+            Stmt::IfElse(cond, body, body2) => {
+                let body = if self.eval(cond, ctx, this).as_bool() { body } else { body2 };
+                self.stack.push(Frame::Block { body, pc: 0 });
+            }
+            Stmt::RepeatUntil(cond, body) => self.stack.push(Frame::Until { cond, body }),
+            Stmt::StopScript => {
+                // Leaves the current procedure or the whole script if not in one.
+                while let Some(frame) = self.stack.pop() {
+                    if matches!(frame, Frame::Call { .. }) {
+                        break;
+                    }
+                }
+            }
+            Stmt::Exit => return Some(IoAction::StopAllScripts),
+            Stmt::StopOtherScripts => return Some(IoAction::StopOtherScripts),
+            Stmt::CloneMyself(_) => return Some(IoAction::CloneMyself),
+            Stmt::LoopYield => {
+                if ctx.loop_yields() && self.in_async() {
+                    return Some(IoAction::LoopYield);
+                }
+            }
+            Stmt::WaitSeconds(seconds) => return Some(IoAction::SleepSecs(self.eval(seconds, ctx, this).as_num())),
+            Stmt::AskAndWait(question) => return Some(IoAction::Ask(self.eval(question, ctx, this).as_str().as_ref().into())),
+            Stmt::SetField(v, e) => {
+                let value = self.eval(e, ctx, this);
+                self.set(Scope::Instance, *v, value, ctx, this);
+            }
+            Stmt::SetGlobal(v, e) => {
+                let value = self.eval(e, ctx, this);
+                self.set(Scope::Global, *v, value, ctx, this);
+            }
+            Stmt::ListSet(s, v, i, item) => {
+                let i = self.eval(i, ctx, this).as_num();
+                let item = coerce(self.eval(item, ctx, this), list_item_type(*v));
+                list(*s, *v, ctx, this).replace(i, item);
+            }
+            Stmt::ListPush(s, v, item) => {
+                let item = coerce(self.eval(item, ctx, this), list_item_type(*v));
+                list(*s, *v, ctx, this).push(item);
+            }
+            Stmt::ListClear(s, v) => list(*s, *v, ctx, this).clear(),
+            Stmt::ListRemoveIndex(s, v, i) => {
+                let i = self.eval(i, ctx, this).as_num();
+                list(*s, *v, ctx, this).remove(i);
+            }
+            Stmt::BuiltinRuntimeCall(name, args, _) => {
+                let args: Vec<_> = args.iter().map(|e| self.eval(e, ctx, this)).collect();
+                call_builtin(ctx, name, &args);
+            }
+            Stmt::CallCustom(name, args) => {
+                let proc = project().targets[this.target].lookup_proc(name).unwrap();
+                let args = proc.args.iter().zip(args.iter())
+                    .map(|(v, e)| (*v, coerce(self.eval(e, ctx, this), ty(*v))))
+                    .collect();
+                self.stack.push(Frame::Call { args, needs_async: proc.needs_async });
+                self.stack.push(Frame::Block { body: &proc.body, pc: 0 });
+            }
+            Stmt::BroadcastWait(name) => {
+                let msg = match name {
+                    Expr::Message(msg) => *msg,
+                    _ => project().triggers_by_name.get(self.eval(name, ctx, this).as_str().as_ref()).copied(),
+                };
+                // Nothing receives an unknown message so there's nothing to wait for.
+                if let Some(msg) = msg {
+                    return Some(IoAction::BroadcastWait(msg));
+                }
+            }
+            // The parser already reported a warning for unknown opcodes. Same as backend::rust, they do nothing.
+            Stmt::Empty | Stmt::UnknownOpcode(_) => {}
+        }
+        None
+    }
+
+    fn eval<R: RenderBackend<Program>>(&self, expr: &'static Expr, ctx: &mut Ctx<R>, this: &mut Instance) -> Poly where Program: ScratchProgram<R, Msg = VarId, Globals = Instance> {
+        match expr {
+            Expr::Bin(BinOp::And, a, b) => Poly::Bool(self.eval(a, ctx, this).as_bool() && self.eval(b, ctx, this).as_bool()),
+            Expr::Bin(BinOp::Or, a, b) => Poly::Bool(self.eval(a, ctx, this).as_bool() || self.eval(b, ctx, this).as_bool()),
+            Expr::Bin(op, a, b) => {
+                let (a, b) = (self.eval(a, ctx, this), self.eval(b, ctx, this));
+                match op {
+                    BinOp::EQ => Poly::Bool(a == b),
+                    BinOp::StrJoin => Poly::Str(a.as_str().join(b.as_str())),
+                    _ => {
+                        let (a, b) = (a.as_num(), b.as_num());
+                        match op {
+                            BinOp::Add => Poly::Num(a + b),
+                            BinOp::Sub => Poly::Num(a - b),
+                            BinOp::Mul => Poly::Num(a * b),
+                            BinOp::Div => Poly::Num(a / b),
+                            BinOp::Mod => Poly::Num(a % b),
+                            BinOp::Pow => Poly::Num(a.powf(b)),
+                            BinOp::Random => Poly::Num(dyn_rand(a, b)),
+                            BinOp::GT => Poly::Bool(a > b),
+                            BinOp::LT => Poly::Bool(a < b),
+                            _ => unreachable!(),
+                        }
+                    }
+                }
+            }
+            Expr::Un(op, e) => {
+                let value = self.eval(e, ctx, this);
+                match op {
+                    UnOp::Not => Poly::Bool(!value.as_bool()),
+                    UnOp::StrLen => Poly::Num(value.as_str().len()),
+                    UnOp::SuffixCall(name) => Poly::Num(suffix_call(name, value.as_num())),
+                }
+            }
+            Expr::GetField(v) => self.get(Scope::Instance, *v, ctx, this),
+            Expr::GetGlobal(v) => self.get(Scope::Global, *v, ctx, this),
+            Expr::GetArgument(v) => self.get(Scope::Argument, *v, ctx, this),
+            Expr::ListGet(s, v, i) => {
+                let i = self.eval(i, ctx, this).as_num();
+                list(*s, *v, ctx, this)[i].clone()
+            }
+            Expr::ListLen(s, v) => Poly::Num(list(*s, *v, ctx, this).len()),
+            Expr::StringGetIndex(string, index) => {
+                let (string, index) = (self.eval(string, ctx, this), self.eval(index, ctx, this));
+                Poly::Str(string.as_str().get_index(index.as_num()))
+            }
+            Expr::Empty => Poly::Empty,
+            Expr::IsNum(e) => Poly::Bool(self.eval(e, ctx, this).is_num()),
+            Expr::BuiltinRuntimeGet(name, _) => call_builtin(ctx, name, &[]),
+            Expr::Literal(lit) => literal(lit),
+            Expr::Message(Some(msg)) => Poly::Str(Str::from(project().scratch_names[msg.0].clone())),
+            // Unknown reporters were already reported by the parser. List literals are only used for default values.
+            Expr::Message(None) | Expr::UnknownExpr(_) | Expr::ListLiteral(_) => Poly::Empty,
+        }
+    }
+
+    fn get<R: RenderBackend<Program>>(&self, scope: Scope, v: VarId, ctx: &mut Ctx<R>, this: &mut Instance) -> Poly where Program: ScratchProgram<R, Msg = VarId, Globals = Instance> {
+        let value = match scope {
+            Scope::Instance => &this.vars[&v],
+            Scope::Global => &ctx.globals.vars[&v],
+            Scope::Argument => return self.args()[&v].clone(),
+        };
+        match value {
+            Value::Scalar(value) => value.clone(),
+            // TODO: scratch joins the items. Same as backend::rust for now.
+            Value::List(list) => list[1.0].clone(),
+        }
+    }
+
+    fn set<R: RenderBackend<Program>>(&mut self, scope: Scope, v: VarId, value: Poly, ctx: &mut Ctx<R>, this: &mut Instance) where Program: ScratchProgram<R, Msg = VarId, Globals = Instance> {
+        match scope {
+            Scope::Instance => { this.vars.insert(v, store(v, value)); }
+            Scope::Global => { ctx.globals.vars.insert(v, store(v, value)); }
+            Scope::Argument => { self.args_mut().insert(v, coerce(value, ty(v))); }
+        }
+    }
+
+    /// Scripts are always async. Same as the compiled code, see Stmt::LoopYield.
+    fn in_async(&self) -> bool {
+        self.stack.iter().rev().find_map(|frame| match frame {
+            Frame::Call { needs_async, .. } => Some(*needs_async),
+            _ => None,
+        }).unwrap_or(true)
+    }
+
+    fn args(&self) -> &HashMap<VarId, Poly> {
+        self.stack.iter().rev().find_map(|frame| match frame {
+            Frame::Call { args, .. } => Some(args),
+            _ => None,
+        }).expect("Argument used outside a procedure")
+    }
+
+    fn args_mut(&mut self) -> &mut HashMap<VarId, Poly> {
+        self.stack.iter_mut().rev().find_map(|frame| match frame {
+            Frame::Call { args, .. } => Some(args),
+            _ => None,
+        }).expect("Argument used outside a procedure")
+    }
+}
+
+/// The whole script is one state since the stack says where it's up to.
+impl<R: RenderBackend<Program>> Machine<Program, R> for Thread where Program: ScratchProgram<R, Msg = VarId, Globals = Instance> {
+    fn resume(&mut self, ctx: &mut Ctx<R>, this: &mut dyn Any, _: NonZeroU16) -> FutRes<Program, R> {
+        let this = ctx.trusted_cast::<Instance>(this);
+        match self.run(ctx, this) {
+            Some(action) => (action, Some(state!(1))),
+            None => (IoAction::None, None),
+        }
+    }
+}
+
+fn list<'a, R: RenderBackend<Program>>(scope: Scope, v: VarId, ctx: &'a mut Ctx<R>, this: &'a mut Instance) -> &'a mut List<Poly> where Program: ScratchProgram<R, Msg = VarId, Globals = Instance> {
+    let value = match scope {
+        Scope::Instance => this.vars.get_mut(&v),
+        Scope::Global => ctx.globals.vars.get_mut(&v),
+        Scope::Argument => unreachable!("Lists can't be arguments"),
+    };
+    match value {
+        Some(Value::List(list)) => list,
+        _ => panic!("{} is not a list", project().scratch_names[v.0]),
+    }
+}
+
+fn ty(v: VarId) -> Option<SType> {
+    project().expected_types[v.0]
+}
+
+fn list_item_type(v: VarId) -> Option<SType> {
+    ty(v).and_then(SType::list_item)
+}
+
+/// What the compiled code would hold in a variable of this type.
+fn store(v: VarId, value: Poly) -> Value {
+    match ty(v) {
+        Some(t) if t.is_list() => Value::List(List::from(vec![coerce(value, t.list_item())])),
+        t => Value::Scalar(coerce(value, t)),
+    }
+}
+
+/// The conversion the compiled code would do when writing a value of unknown type to this type.
+fn coerce(value: Poly, t: Option<SType>) -> Poly {
+    match t {
+        Some(SType::Number) => Poly::Num(value.as_num()),
+        Some(SType::Integer) => Poly::Num(value.as_num() as i64 as f64),
+        Some(SType::Str) => Poly::Str(value.as_str()),
+        Some(SType::Bool) => Poly::Bool(value.as_bool()),
+        _ => value,
+    }
+}
+
+fn literal(lit: &Lit) -> Poly {
+    match lit {
+        Lit::Num(n) => Poly::Num(*n),
+        Lit::Str(s) => Poly::Str(Str::from(s.clone())),
+        Lit::Bool(b) => Poly::Bool(*b),
+    }
+}
+
+/// The method names parse gives operator_mathop. It reports any others as an UnknownExpr.
+fn suffix_call(name: &str, v: f64) -> f64 {
+    match name {
+        "abs" => v.abs(),
+        "floor" => v.floor(),
+        "ceil" => v.ceil(),
+        "sqrt" => v.sqrt(),
+        "round" => v.round(),
+        "ln" => v.ln(),
+        "log10" => v.log10(),
+        "exp" => v.exp(),
+        "to_radians().sin" => v.to_radians().sin(),
+        "to_radians().cos" => v.to_radians().cos(),
+        "to_radians().tan" => v.to_radians().tan(),
+        "asin().to_degrees" => v.asin().to_degrees(),
+        "acos().to_degrees" => v.acos().to_degrees(),
+        "atan().to_degrees" => v.atan().to_degrees(),
+        _ => 0.0,
+    }
+}
+
+/// Calls the FrameCtx method for each entry in runtime::builtin_registry!, converting the arguments like the compiled code would.
+macro_rules! registry {
+    (@arg Number $v:expr) => { $v.as_num() };
+    (@arg Integer $v:expr) => { $v.as_num() as i64 };
+    (@arg Str $v:expr) => { $v.as_str() };
+    (@arg Bool $v:expr) => { $v.as_bool() };
+    (@arg Poly $v:expr) => { $v.clone() };
+    (@ret [] $call:expr) => {{ $call; Poly::Empty }};
+    (@ret [Integer] $call:expr) => { Poly::Num($call as f64) };
+    (@ret [$t:ident] $call:expr) => { Poly::from($call) };
+    ($($name:ident($($arg:ident: $t:ident),*) $(-> $ret:ident)?;)*) => {
+        fn call_builtin<R: RenderBackend<Program>>(ctx: &mut Ctx<R>, name: &str, args: &[Poly]) -> Poly where Program: ScratchProgram<R, Msg = VarId, Globals = Instance> {
+            match name {
+                $(stringify!($name) => {
+                    #[allow(unused_mut, unused_variables)]
+                    let mut args = args.iter();
+                    $(let $arg = registry!(@arg $t args.next().unwrap());)*
+                    registry!(@ret [$($ret)?] ctx.$name($($arg),*))
+                })*
+                _ => unreachable!("{name} is not in the builtin registry"),
+            }
+        }
+    };
+}
+
+runtime::builtin_registry!(registry);
+
+/// Runs without a window. Say prints to stdout and ask reads a line from stdin.
+pub type Console = console::BackendImpl<Program>;
@@ -1 +1,3 @@
 pub mod rust;
+#[cfg(feature = "interp")]
+pub mod interp;
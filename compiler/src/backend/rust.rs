@@ -1,14 +1,15 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt::{Display, Formatter};
 use std::mem;
-use crate::ast::{BinOp, Expr, Proc, Project, Scope, Sprite, Stmt, SType, Trigger, UnOp, VarId};
-use crate::parse::{infer_type, runtime_prototype, safe_str};
+use crate::ast::{BinOp, Expr, Lit, Proc, Project, Scope, Sprite, Stmt, SType, Trigger, UnOp, VarId};
+use crate::diagnostic::{Diagnostic, Location, Severity};
+use crate::parse::{infer_type, int_literal, is_int_arith, runtime_prototype};
 use crate::{AssetPackaging, Target};
-use crate::template;
 
 // TODO: it would be more elegant if changing render backend was just a feature flag, not a src change. same for AssetPackaging but thats harder cause it only needs to copy the files there for embed
 // TODO: codegen fetch (its easy)
-pub fn emit_rust(project: &Project, backend: Target, _assets: AssetPackaging) -> String {
+/// Also returns warnings for blocks the generated code can't do. Those compile to nothing.
+pub fn emit_rust(project: &Project, backend: Target, _assets: AssetPackaging) -> (String, Vec<Diagnostic>) {
     let msgs: HashSet<Trigger> = project.targets.
         iter()
         .flat_map(|target|
@@ -24,21 +25,64 @@ pub fn emit_rust(project: &Project, backend: Target, _assets: AssetPackaging) ->
     }).collect();
     assert_eq!(msg_fields.len(), msgs.len(), "lost some to mangling dup names");
     let msg_fields: String = msg_fields.into_iter().collect();
-    let body: String = project.targets.iter().map(|target| Emit { project, target, triggers: HashMap::new(), current_is_async: false, current: None, loop_var_count: 0, has_captures: false }.emit()).collect();
+    let mut machine_loaders = String::new();
+    let mut diagnostics = vec![];
+    let body: String = project.targets.iter().map(|target| {
+        let mut emit = Emit { project, target, triggers: HashMap::new(), current_is_async: false, current: None, loop_var_count: 0, machines: String::new(), machine_loaders: String::new(), diagnostics: vec![] };
+        let src = emit.emit();
+        machine_loaders += &emit.machine_loaders;
+        diagnostics.append(&mut emit.diagnostics);
+        src
+    }).collect();
 
     let sprites: String = project.targets
         .iter()
         .filter(|target| !target.is_stage)  // TODO: wrong cause stage can have scripts but im using it as special magic globals so need to rethink.
         .map(|target| format!("Box::new({}::default()), ", target.name))
         .collect();
+    let all_msgs: String = msgs.iter().map(|t| {
+        let name = match t {
+            Trigger::Message(name) => name,
+            _ => unreachable!(),
+        };
+        format!("Msg::{}, ", trigger_msg_ident(project, *name))
+    }).collect();
     let msg_names: String = msgs.iter().map(|t| {
         let name = match t {
             Trigger::Message(name) => name,
             _ => unreachable!(),
         };
-        format!("\"{}\"=>Msg::{}, \n", project.var_names[name.0].escape_default(), trigger_msg_ident(project, *name))
+        format!("\"{}\"=>Msg::{}, \n", project.scratch_names[name.0].escape_default(), trigger_msg_ident(project, *name))
     }).collect();
 
+    // The (sprite, hat) of each script for each trigger. Top layer first and hats in the order Emit::emit numbers them.
+    let mut listeners: BTreeMap<String, Vec<(usize, usize)>> = BTreeMap::new();
+    let targets: Vec<_> = project.targets.iter().filter(|target| !target.is_stage).collect();
+    for (i, target) in targets.iter().enumerate().rev() {
+        let mut hats: HashMap<Trigger, usize> = HashMap::new();
+        for func in &target.scripts {
+            let hat = hats.entry(func.start).or_default();
+            listeners.entry(format_trigger(project, &func.start)).or_default().push((i, *hat));
+            *hat += 1;
+        }
+    }
+    let receivers: String = listeners.iter()
+        .map(|(trigger, scripts)| format!("{trigger} => &{scripts:?},\n"))
+        .collect();
+
+    // Indexes into the stage's var list (see Emit::emit).
+    let cloud_vars: String = project.targets.iter()
+        .filter(|target| target.is_stage)
+        .flat_map(|target| target.fields.iter().enumerate())
+        .filter(|(_, v)| project.cloud_vars.contains(v))
+        .map(|(i, _)| format!("{i}, "))
+        .collect();
+
+    let c = &project.config;
+    let max_clones = c.runtimeOptions.maxClones.map_or(String::from("usize::MAX"), |n| (n as usize).to_string());
+    let config = format!("framerate: {}, turbo: {}, interpolation: {}, hq_pen: {}, fencing: {}, max_clones: {max_clones}, misc_limits: {}, width: {}, height: {},",
+        c.framerate, c.turbo, c.interpolation, c.hq, c.runtimeOptions.fencing, c.runtimeOptions.miscLimits, c.width, c.height);
+
     // TODO: move some of costume resolution into parse and dont just pass it through ast
     // TODO: dups? names need to be unique to the spite but make sure not to include same assets twice.
     let costumes: Vec<_> = project.targets
@@ -51,13 +95,20 @@ pub fn emit_rust(project: &Project, backend: Target, _assets: AssetPackaging) ->
     }
 
     // assert_eq!(assets, AssetPackaging::Embed);
-    let costume_names: String = costumes.iter().map(|(i, c)| format!("\"{}\" => Some({i}),\n", c.name.escape_default())).collect();
+    // Where each sprite's costumes start in costume_includes and their names. Same order as create_initial_state.
+    let mut first = 0;
+    let costume_names: String = project.targets.iter().filter_map(|target| {
+        let names: String = target.costumes.iter().map(|c| format!("\"{}\", ", c.name.escape_default())).collect();
+        let entry = (!target.is_stage).then(|| format!("({first}, &[{names}]),\n"));
+        first += target.costumes.len();
+        entry
+    }).collect();
     let costume_includes: String = costumes.iter().map(|(_, c)| format!("ScratchAsset::Embed(include_bytes!(\"assets/{}\")),", c.md5ext)).collect();
 
     let backend_str = backend.code_name();
     // TODO: allow override?
     // TODO: fix redundant template syntax
-    template!(
+    let src = template!(
         "../data/main_rs",
         backend_str=backend_str,
         sprites=sprites,
@@ -65,12 +116,18 @@ pub fn emit_rust(project: &Project, backend: Target, _assets: AssetPackaging) ->
         costume_names=costume_names,
         msg_fields=msg_fields,
         msg_names=msg_names,
+        all_msgs=all_msgs,
+        machine_loaders=machine_loaders,
+        cloud_vars=cloud_vars,
+        config=config,
+        receivers=receivers,
         body=body
-    )
+    );
+    (src, diagnostics)
 }
 
-fn trigger_msg_ident(project: &Project, v: VarId) -> String {
-    format!("M{}_{}", v.0, safe_str(&project.var_names[v.0]))
+fn trigger_msg_ident(project: &Project, v: VarId) -> &str {
+    &project.var_names[v.0]
 }
 
 
@@ -85,7 +142,7 @@ fn format_trigger(project: &Project, value: &Trigger) -> String {
 fn debug_trigger(project: &Project, value: &Trigger) -> String {
     match value {
         Trigger::FlagClicked => "Event: Flag Clicked".to_string(),
-        Trigger::Message(v) => format!("Event: {}", &project.var_names[v.0].escape_default()),
+        Trigger::Message(v) => format!("Event: {}", project.scratch_names[v.0].escape_default()),
         Trigger::SpriteClicked => "Event: Sprite Clicked".to_string(),
     }
 }
@@ -98,10 +155,20 @@ struct Emit<'src> {
     // This is used for loop closures cloning arguments. Scripts don't have arguments so its fine.
     current: Option<&'src Proc>,
     loop_var_count: usize,
-    has_captures: bool,
+    /// The struct for each FutMachine. They go after the sprite.
+    machines: String,
+    /// Match arms for ScratchProgram::load_machine.
+    machine_loaders: String,
+    diagnostics: Vec<Diagnostic>,
 }
 
 impl<'src> Emit<'src> {
+    /// A block the generated code can't do. The caller emits something that does nothing instead.
+    fn warn(&mut self, block_id: &str, opcode: &str, message: &str) {
+        let at = Location { sprite: Some(self.target.scratch_name.clone()), block_id: Some(block_id.to_string()), opcode: Some(opcode.to_string()) };
+        self.diagnostics.push(at.diagnostic(Severity::Warning, message.to_string()));
+    }
+
     fn emit(&mut self) -> String {
         let mut var_names = String::new();
         let mut visit_vars = String::new();
@@ -115,17 +182,20 @@ impl<'src> Emit<'src> {
             fields += &format!("   {}: {},\n", name, self.inferred_type_name(*v));
             let ty = self.inferred_type(*v);
             let constructor= &match ty {
-                SType::Number => format!("Num(&"),
-                SType::Bool => format!("Bool(&"),
-                SType::Str => format!("Str(&"),
-                SType::ListPoly => format!("List(&"),
-                SType::Poly => format!("Poly(&"),
+                SType::Integer => "Int(&".to_string(),
+                SType::Number => "Num(&".to_string(),
+                SType::Bool => "Bool(&".to_string(),
+                SType::Str => "Str(&".to_string(),
+                SType::ListUnknown | SType::ListPoly => "List(&".to_string(),
+                SType::ListNumber => "NumList(&".to_string(),
+                SType::ListStr => "StrList(&".to_string(),
+                SType::Poly => "Poly(&".to_string(),
             };
-            var_names += &format!("\"{}\",", name.escape_default());
-            visit_vars += &format!("{i} => V::{constructor}self.{name}),");   // TODO: debug_assert safe string
+            var_names += &format!("\"{}\",", self.project.scratch_names[v.0].escape_default());
+            visit_vars += &format!("{i} => V::{constructor}self.{name}),");
             visit_vars_mut += &format!("{i} => V::{constructor}mut self.{name}),");
             let value = match value {
-                None | Some(Expr::Empty) => format!("Default::default()"),
+                None | Some(Expr::Empty) => "Default::default()".to_string(),
                 Some(e) => self.emit_expr(e, Some(ty)).text,
             };
             default_fields += &format!("   {}: {},\n", name, value);
@@ -137,9 +207,8 @@ impl<'src> Emit<'src> {
         for func in &self.target.scripts {
             self.current_is_async = true;  // Scripts are always async.
             self.current = None;
-            self.has_captures = false;
             let body = self.emit_block(&func.body);
-            let src = format!("{{ {} }}", FutMachine::from(body.clone()).to_src(&debug_trigger(self.project, &func.start), self.has_captures));
+            let src = format!("{{ {} }}", self.emit_machine(FutMachine::from(body.clone()), &debug_trigger(self.project, &func.start), &func.machine, vec![]));
             // TODO: idk why im in a functional mood rn
             let handler = match self.triggers.remove(&func.start) {
                 Some(mut prev) => {
@@ -148,22 +217,18 @@ impl<'src> Emit<'src> {
                 },
                 None => vec![src],
             };
-            self.triggers.insert(func.start.clone(), handler);
+            self.triggers.insert(func.start, handler);
         }
 
         let mut async_handlers = String::new();
 
         for (trigger, scripts) in &self.triggers {
-            let action = if scripts.len() == 1 {
-                format!("{}", scripts[0])
-            } else {
-                format!("IoAction::Concurrent(vec![{}])", scripts.join(","))
-            };
-
-            async_handlers.push_str(&format!(
-                "{trigger} => {action},",
-                trigger = format_trigger(&self.project, trigger),
-            ));
+            for (hat, script) in scripts.iter().enumerate() {
+                async_handlers.push_str(&format!(
+                    "({trigger}, {hat}) => {script},",
+                    trigger = format_trigger(self.project, trigger),
+                ));
+            }
         }
 
         // TODO: wrong? var defaults are in the json
@@ -177,14 +242,65 @@ impl<'src> Emit<'src> {
             visit_vars=visit_vars,
             var_names=var_names,
             visit_vars_mut=visit_vars_mut,
-            default_fields=default_fields
+            default_fields=default_fields,
+            machines=self.machines
         )
     }
 
+    /// Adds the machine's struct to self.machines and returns an expression that starts it.
+    /// Args are fields set when it starts. The rest of the fields start at zero.
+    fn emit_machine(&mut self, machine: FutMachine, name: &str, id: &str, args: Vec<(String, &'static str)>) -> String {
+        let arg_names: Vec<_> = args.iter().map(|(arg, _)| arg.clone()).collect();
+        let has_locals = !machine.locals.is_empty();
+        let fields: Vec<_> = args.into_iter().chain(machine.locals.iter().map(|v| (v.clone(), "usize"))).collect();
+        let needs_alloc = !fields.is_empty();
+
+        let declare: String = fields.iter().map(|(v, ty)| format!("{v}: {ty}, ")).collect();
+        let names: String = fields.iter().map(|(v, _)| format!("{v}, ")).collect();
+        let mut_names: String = fields.iter().map(|(v, _)| format!("mut {v}, ")).collect();
+        let save: String = fields.iter().map(|(v, _)| format!("self.{v}.save(out);\n")).collect();
+        let load: String = fields.iter().map(|(v, _)| format!("{v}: Save::load(input), ")).collect();
+        let body = machine.into_src(self.target.name.as_str());
+
+        // The fields are moved into locals so the body is the same code a closure capturing them would be.
+        self.machines += &format!(r#"
+#[derive(Default)]
+struct {id} {{ {declare} }}
+
+impl Machine<Stage, Backend> for {id} {{
+    fn resume(&mut self, ctx: &mut Ctx, this: &mut dyn Any, s: std::num::NonZeroU16) -> FutRes<Stage, Backend> {{
+        let {id} {{ {mut_names} }} = std::mem::take(self);
+        let __res = (|| -> FutRes<Stage, Backend> {{
+            {body}
+        }})();
+        *self = {id} {{ {names} }};
+        __res
+    }}
+
+    #[cfg(feature = "save")]
+    fn save(&self, out: &mut SaveWriter) {{
+        out.str("{id}");
+        {save}
+    }}
+}}
+
+#[cfg(feature = "save")]
+impl {id} {{
+    fn load(input: &mut SaveReader) -> Self {{
+        Self {{ {load} }}
+    }}
+}}
+"#);
+        self.machine_loaders += &format!("\"{id}\" => fut_a(\"{name}\", {id}::load(input)),\n");
+
+        let f = if needs_alloc { "fut_a" } else { "fut" };
+        let rest = if has_locals { "..Default::default()" } else { "" };
+        format!("{f}(\"{name}\", {id} {{ {} {rest} }})", arg_names.join(", ") + if arg_names.is_empty() { "" } else { "," })
+    }
+
     fn emit_custom_proc(&mut self, t: &'src Proc) -> String {
         self.current_is_async = t.needs_async;
         self.current = Some(t);
-        self.has_captures = !t.args.is_empty();
         // println!("emit {} async={}", t.name, t.needs_async);
         let args = if t.args.is_empty() {
             "".to_string()
@@ -195,15 +311,17 @@ impl<'src> Emit<'src> {
             format!(", {}", args.join(", "))
         };
         let body = self.emit_block(&t.body);
-        match body.clone().to_sync() {
+        match body.clone().into_sync() {
             None => {
                 assert!(t.needs_async, "expected async fn {} \n{:?}", t.name, body);
                 // TODO: list of reserved variable names that cant be used for args
+                let machine_args = t.args.iter().map(|&v| (self.project.var_names[v.0].clone(), self.inferred_type_name(v))).collect();
+                let src = self.emit_machine(FutMachine::from(body), &format!("Call: {}", t.scratch_name.escape_default()), &t.machine, machine_args);
                 format!(r#"
                     fn {name}(&self{args}) -> IoAction<Stage, Backend> {{
                         {src}
                     }}
-        "#, name=t.name, src=FutMachine::from(body).to_src(&format!("Call: {}", t.name), self.has_captures))
+        "#, name=t.name)
 
 
             },
@@ -218,15 +336,19 @@ impl<'src> Emit<'src> {
     fn emit_stmt(&mut self, stmt: &'src Stmt) -> RustStmt {
         // Implicit return a string of sync code if more complex, use explicit return from the match.
         RustStmt::sync(match stmt {
-            Stmt::BuiltinRuntimeCall(name, args) => {
-                let arg_types: Vec<_> = runtime_prototype(name).unwrap().iter().map(|t| Some(t.clone())).collect();
+            Stmt::BuiltinRuntimeCall(name, args, id) => {
+                let Some(proto) = runtime_prototype(name) else {
+                    self.warn(id, name, "The runtime has no function for this block. It will do nothing.");
+                    return RustStmt::Empty;
+                };
+                let arg_types: Vec<_> = proto.iter().map(|t| Some(*t)).collect();
                 format!("ctx.{}({});\n", name, self.emit_args(args, &arg_types))
             },
             Stmt::SetField(v, e) => {
-                format!("this.{} = {};\n", self.project.var_names[v.0], self.emit_expr(e, self.project.expected_types[v.0].clone()))
+                format!("this.{} = {};\n", self.project.var_names[v.0], self.emit_expr(e, self.project.expected_types[v.0]))
             }
             Stmt::SetGlobal(v, e) => {
-                format!("ctx.globals.{} = {};\n", self.project.var_names[v.0], self.emit_expr(e, self.project.expected_types[v.0].clone()))
+                format!("ctx.globals.{} = {};\n", self.project.var_names[v.0], self.emit_expr(e, self.project.expected_types[v.0]))
             }
             Stmt::If(cond, body) => {
                 return RustStmt::If {
@@ -244,8 +366,8 @@ impl<'src> Emit<'src> {
             }
             Stmt::RepeatUntil(cond, body) => { // TODO: is this supposed to be do while?
                 return RustStmt::Loop {
-                    var_decls: "".to_string(),
-                    init: format!(""),
+                    counter: None,
+                    init: String::new(),
                     body: Box::new(self.emit_block(body)),
                     end_cond: self.emit_expr(cond, Some(SType::Bool)),
                     inc_stmt: "".to_string(),
@@ -254,15 +376,13 @@ impl<'src> Emit<'src> {
             }
             Stmt::RepeatTimes(times, body) => {
                 self.loop_var_count += 1;
-                self.has_captures = true;
                 let id = self.loop_var_count;  // emit_block below invalidates.
                 let body = self.emit_block(body);
-                self.has_captures |= !body.is_sync();
-                let e = self.emit_expr(times, Some(SType::Number));
+                let e = self.emit_times(times);
                 // There are no real locals so can't have name conflicts
                 return RustStmt::Loop {  // TODO: check edge cases of the as usize in scratch
-                    var_decls: format!("let mut i{0} = 0; let mut end{0} = 0;", id),
-                    init: format!("i{0} = 0usize; end{0} = {1} as usize;", id, e),
+                    counter: Some(id),
+                    init: format!("i{0} = 0usize; end{0} = {1};", id, e),
                     body: Box::new(body),
                     end_cond: rval(SType::Bool, format!("(i{0} >= end{0})", id)),
                     inc_stmt: format!("i{0} += 1;", id),
@@ -273,15 +393,18 @@ impl<'src> Emit<'src> {
                 self.loop_var_count += 1;
                 let id = self.loop_var_count;  // emit_block below invalidates.
                 let body = self.emit_block(body);
-                self.has_captures |= !body.is_sync();
                 // There are no real locals so can't have name conflicts
-                let var_ty = self.project.expected_types[v.0].clone().or(Some(SType::ListPoly)).unwrap();
-                let iter_expr = rval(SType::Number, format!("((i{0} + 1) as f64)", id)).coerce(&var_ty);
-                let e = self.emit_expr(times, Some(SType::Number));
+                let var_ty = self.project.expected_types[v.0].unwrap_or(SType::ListPoly);
+                let iter_expr = if var_ty == SType::Integer {
+                    rval(SType::Integer, format!("((i{0} + 1) as i64)", id))
+                } else {
+                    rval(SType::Number, format!("((i{0} + 1) as f64)", id)).coerce(&var_ty)
+                };
+                let e = self.emit_times(times);
                 let v = self.ref_var(*s, *v, true);
                 return RustStmt::Loop {
-                    var_decls: format!("let mut i{0} = 0; let mut end{0} = 0;", id),
-                    init: format!("i{0} = 0usize; end{0} = {1} as usize;", id, e),
+                    counter: Some(id),
+                    init: format!("i{0} = 0usize; end{0} = {1};", id, e),
                     body: Box::new(body),
                     end_cond: rval(SType::Bool, format!("(i{0} >= end{0})", id)),
                     inc_stmt: format!("{1} = {iter_expr}; i{0} += 1;", id, v),
@@ -306,44 +429,81 @@ impl<'src> Emit<'src> {
             }
             Stmt::ListSet(s, v, i, item) => {
                 let list = self.ref_var(*s, *v, true);
-                let index = self.emit_expr(i, Some(SType::Number));
-                let item = self.emit_expr(item, Some(SType::Poly));
+                let index = self.emit_index(i);
+                let item = self.emit_expr(item, Some(self.list_item_type(*v)));
                 format!("let index = {index}; let item = {item}; {list}.replace(index, item);\n")
             },
-            Stmt::ListPush(s, v, item) => format!("{}.push({});\n", self.ref_var(*s, *v, false), self.emit_expr(item, Some(SType::Poly))),
+            Stmt::ListPush(s, v, item) => {
+                let item = self.emit_expr(item, Some(self.list_item_type(*v)));
+                format!("{}.push({});\n", self.ref_var(*s, *v, false), item)
+            },
             Stmt::ListClear(s, v) => format!("{}.clear();\n", self.ref_var(*s, *v, true)),
             Stmt::ListRemoveIndex(s, v, i) =>
-                format!("{}.remove({});\n", self.ref_var(*s, *v, true), self.emit_expr(i, Some(SType::Number))),  // TODO: what happens on OOB?
+                format!("{}.remove({});\n", self.ref_var(*s, *v, true), self.emit_index(i)),  // TODO: what happens on OOB?
             Stmt::BroadcastWait(name) => {
-                // TODO: do the conversion at comptime when possible. it feels important enough to have the check if param is a literal
                 // TODO: happy path if self.target.is_singleton
-                return RustStmt::IoAction(format!("IoAction::BroadcastWait(msg_of({}))", self.emit_expr(name, Some(SType::Str))));
+                let msg = match name {
+                    Expr::Message(Some(v)) => format!("Msg::{}", trigger_msg_ident(self.project, *v)),
+                    Expr::Message(None) => "Msg::InvalidComputedMessage".to_string(),
+                    _ => format!("msg_of({})", self.emit_expr(name, Some(SType::Str))),
+                };
+                return RustStmt::IoAction(format!("IoAction::BroadcastWait({msg})"));
             }
             Stmt::Exit => return RustStmt::IoAction(String::from("IoAction::StopAllScripts")),
+            Stmt::StopOtherScripts => return RustStmt::IoAction(String::from("IoAction::StopOtherScripts")),
+            Stmt::LoopYield => {
+                if !self.current_is_async {
+                    return RustStmt::Empty;
+                }
+                return RustStmt::If {
+                    cond: String::from("ctx.loop_yields()"),
+                    if_true: Box::new(RustStmt::IoAction(String::from("IoAction::LoopYield"))),
+                    if_false: None,
+                };
+            }
             Stmt::WaitSeconds(seconds) => {
                 return RustStmt::IoAction(format!("IoAction::SleepSecs({})", self.emit_expr(seconds, Some(SType::Number))))
             }
             Stmt::AskAndWait(question) => {
                 return RustStmt::IoAction(format!("IoAction::Ask({}.as_ref().into())", self.emit_expr(question, Some(SType::Str))))
             }
-            Stmt::Empty => return RustStmt::Empty,
-            _ => format!("todo!(r#\"{:?}\"#);\n", stmt)  // TODO: log comptime warning
+            Stmt::CloneMyself(id) => {
+                self.warn(id, "control_create_clone_of", "Clones are not supported yet. It will do nothing.");
+                return RustStmt::Empty;
+            }
+            Stmt::Empty | Stmt::UnknownOpcode(_) => return RustStmt::Empty,  // The parser already reported a warning for unknown opcodes
         })
     }
 
     fn arg_types(&self, proc_name: &str) -> Vec<Option<SType>> {
         // If I was being super fancy, we know they're sequential so could return a slice of the original vec with 'src.
         self.target.procedures.iter().find(|p| p.name == proc_name).unwrap()
-            .args.iter().map(|v| self.project.expected_types[v.0].clone()).collect()
+            .args.iter().map(|v| self.project.expected_types[v.0]).collect()
     }
 
     /// Comma seperated
     fn emit_args(&mut self, args: &'src [Expr], arg_types: &[Option<SType>]) -> String {
         // TODO: I shouldn't have to allocate the vec
-        let args = args.iter().zip(arg_types.iter()).map(|(e, t)| self.emit_expr(e, t.clone()).text).collect::<Vec<_>>();
+        let args = args.iter().zip(arg_types.iter()).map(|(e, t)| self.emit_expr(e, *t).text).collect::<Vec<_>>();
         args.join(", ")
     }
 
+    /// Number of iterations as a usize.
+    fn emit_times(&mut self, times: &'src Expr) -> String {
+        if infer_type(self.project, times) == Some(SType::Integer) {
+            // Casting a negative f64 saturates to zero but an i64 would wrap.
+            format!("{}.max(0) as usize", self.emit_expr(times, Some(SType::Integer)))
+        } else {
+            format!("{} as usize", self.emit_expr(times, Some(SType::Number)))
+        }
+    }
+
+    /// List indexes skip the float conversion if they're already an Integer.
+    fn emit_index(&mut self, i: &'src Expr) -> RustValue {
+        let t = if infer_type(self.project, i) == Some(SType::Integer) { SType::Integer } else { SType::Number };
+        self.emit_expr(i, Some(t))
+    }
+
     fn emit_block(&mut self, args: &'src [Stmt]) -> RustStmt {
         let mut block = RustStmt::Empty;
         for s in args {
@@ -379,10 +539,16 @@ impl<'src> Emit<'src> {
                     BinOp::StrJoin => SType::Str,
                 };
 
+                let int_arith = is_int_arith(self.project, op, rhs, lhs);
+                let out_t = if int_arith && out_t == SType::Number { SType::Integer } else { out_t };
+
                 // TODO: this is kinda icky
-                let arg_t = if *op == BinOp::EQ {
-                    let rhs_t = infer_type(&self.project, rhs);
-                    let lhs_t = infer_type(&self.project, lhs);
+                let arg_t = if int_arith {
+                    SType::Integer
+                } else if *op == BinOp::EQ {
+                    // Comparing an Integer to a Number doesn't need to go through Poly.
+                    let rhs_t = infer_type(self.project, rhs).map(widen_int);
+                    let lhs_t = infer_type(self.project, lhs).map(widen_int);
                     let res = rhs_t.or(lhs_t).unwrap_or(SType::Poly);
                     match (lhs_t, rhs_t) {
                         (Some(lhs_t), Some(rhs_t)) => {
@@ -397,7 +563,15 @@ impl<'src> Emit<'src> {
                 let (a, b) = (self.emit_expr(rhs, Some(arg_t)), self.emit_expr(lhs, Some(arg_t)));
 
                 let text = match op {
-                    BinOp::Random => format!("dyn_rand({}, {})", a, b),
+                    // Constant bounds don't need to check for whole numbers every call.
+                    BinOp::Random => match (rhs.as_ref(), lhs.as_ref()) {
+                        (Expr::Literal(Lit::Num(min)), Expr::Literal(Lit::Num(max))) => if min.round() == *min && max.round() == *max {
+                            format!("rand_int({}, {})", a, b)
+                        } else {
+                            format!("rand_float({}, {})", a, b)
+                        },
+                        _ => format!("dyn_rand({}, {})", a, b),
+                    },
                     BinOp::Pow => format!("{}.powf({})", a, b),
                     BinOp::StrJoin => format!("({}.join({}))", a, b),
                     _ => {
@@ -435,17 +609,15 @@ impl<'src> Emit<'src> {
             Expr::IsNum(e) => {
                 rval(SType::Bool, format!("{}.is_num()", self.emit_expr(e, Some(SType::Poly))))
             }
-            Expr::Literal(s) => {
-                let (value, found) = match s.as_str() {
-                    "true" | "false" => (s.parse::<bool>().unwrap().to_string(), SType::Bool),
-                    "Infinity" => ("f64::INFINITY".to_string(), SType::Number),
-                    "-Infinity" => ("f64::NEG_INFINITY".to_string(), SType::Number),
-                    "" => unreachable!("Empty string should parse as Expr::Empty"),
-                    _ => match s.parse::<f64>() {
-                        // Brackets because I'm not sure of precedence for negative literals
-                        Ok(v) => (format!("({}f64)", v), SType::Number),
-                        Err(_) => (format!("Str::from(\"{}\")", s.escape_default()), SType::Str),
-                    }
+            Expr::Literal(lit) => {
+                let (value, found) = match lit {
+                    // Do the conversion at comptime.
+                    _ if t == Some(SType::Str) => (format!("Str::from(\"{}\")", lit.to_string().escape_default()), SType::Str),
+                    _ if t == Some(SType::Integer) && int_literal(lit).is_some() => (format!("({}i64)", int_literal(lit).unwrap()), SType::Integer),
+                    Lit::Num(v) => (num_literal(*v), SType::Number),
+                    Lit::Str(_) if t == Some(SType::Number) => (num_literal(lit.num()), SType::Number),
+                    Lit::Str(s) => (format!("Str::from(\"{}\")", s.escape_default()), SType::Str),
+                    Lit::Bool(b) => (b.to_string(), SType::Bool),
                 };
                 rval(found, value)
             },
@@ -454,30 +626,47 @@ impl<'src> Emit<'src> {
                 rval(SType::Number, e)
             },
             Expr::ListGet(s, v, i) => {
-                let value = format!("{}[{}]", self.ref_var(*s, *v, true), self.emit_expr(i, Some(SType::Number)));
-                rval(SType::Poly, value)
+                let index = self.emit_index(i);
+                let value = list_get(&self.ref_var(*s, *v, true), index, self.inferred_type(*v));
+                if value.ty == SType::Str && t == Some(SType::Number) {
+                    // Scratch lets you do math on strings read from a list.
+                    rval(SType::Poly, format!("Poly::from({value})"))
+                } else {
+                    value
+                }
             },
-            Expr::BuiltinRuntimeGet(name) => {
-                let found = infer_type(self.project, expr).unwrap_or_else(|| panic!("Failed to infer return type of BuiltinRuntimeGet {name}"));
+            Expr::BuiltinRuntimeGet(name, id) => {
+                let Some(found) = infer_type(self.project, expr) else {
+                    self.warn(id, name, "The runtime has no getter for this reporter. It will always be empty.");
+                    return self.emit_expr(&Expr::Empty, t);
+                };
                 rval(found, format!("ctx.{}()", name))
             },
             Expr::StringGetIndex(string, index) => {
                 let value = format!("{}.get_index({})", self.emit_expr(string, Some(SType::Str)), self.emit_expr(index, Some(SType::Number)));
                 rval(SType::Str, value)
             }
-            Expr::Empty => rval(t.unwrap(), match t {
+            // The parser already reported a warning.
+            Expr::Empty | Expr::UnknownExpr(_) => rval(t.unwrap_or(SType::Poly), match t {
                 None | Some(SType::Poly) => "Poly::Empty",
+                Some(SType::Integer) => "0i64",
                 Some(SType::Number) => "0.0f64",
                 Some(SType::Str) => "Str::from(\"\")",
                 Some(SType::Bool) => "false",
-                Some(SType::ListPoly) => unreachable!("Null list."),
+                Some(SType::ListUnknown | SType::ListNumber | SType::ListStr | SType::ListPoly) => unreachable!("Null list."),
             }.to_string()),
             Expr::ListLiteral(data) => {  // Only used for default values.
                 // rustc fucking cannot parse 200k list literal
                 let items: Vec<_> = data.iter().map(ToString::to_string).collect();
-                rval(SType::ListPoly, format!("str_to_poly_list(\"{}\")", items.join(",")))
+                if t == Some(SType::ListNumber) {
+                    rval(SType::ListNumber, format!("str_to_num_list(\"{}\")", items.join(",")))
+                } else {
+                    rval(SType::ListPoly, format!("str_to_poly_list(\"{}\")", items.join(",")))
+                }
             }
-            _ => rval(t.unwrap(), format!("todo!(r#\"{:?}\"#)", expr))
+            // A broadcast input that isn't in a broadcast block. Same as the interpreter, it's just the name.
+            Expr::Message(Some(v)) => rval(SType::Str, format!("Str::from(\"{}\")", self.project.scratch_names[v.0].escape_default())),
+            Expr::Message(None) => return self.emit_expr(&Expr::Empty, t),
         };
         value.coerce_m(&t)
     }
@@ -486,7 +675,7 @@ impl<'src> Emit<'src> {
         let value = match scope {
             Scope::Instance => format!("this.{}", self.project.var_names[v.0]),
             Scope::Global => format!("ctx.globals.{}", self.project.var_names[v.0]),
-            Scope::Argument => format!("{}", self.project.var_names[v.0]),
+            Scope::Argument => self.project.var_names[v.0].to_string(),
         };
         if place_expr {
             value
@@ -506,9 +695,14 @@ impl<'src> Emit<'src> {
     fn inferred_type_name(&self, v: VarId) -> &'static str {
         match &self.project.expected_types[v.0] {
             None => "Poly /* guess */",
-            Some(t) => type_name(t.clone()),
+            Some(t) => type_name(*t),
         }
     }
+    /// Poly if nothing has been written to the list.
+    fn list_item_type(&self, v: VarId) -> SType {
+        self.inferred_type(v).list_item().unwrap_or(SType::Poly)
+    }
+
     fn inferred_type(&self, v: VarId) -> SType {
         match &self.project.expected_types[v.0] {
             None => SType::Poly,
@@ -532,7 +726,8 @@ enum RustStmt {
     Block(Vec<RustStmt>),
     IoAction(String),
     Loop {
-        var_decls: String,
+        /// Loops with a counter use the locals i{n} and end{n}.
+        counter: Option<usize>,
         init: String,
         body: Box<RustStmt>,
         end_cond: RustValue,
@@ -567,39 +762,73 @@ impl RustValue {
         }
         if want == &SType::Poly {
             assert!(!self.text.starts_with("Poly::from"));
-            return rval(*want, match &self.ty {
+            rval(*want, match &self.ty {
+                &SType::Integer => format!("Poly::from({} as f64)", self.text),
                 &SType::Number | &SType::Bool => format!("Poly::from({})", self.text),
                 &SType::Str => format!("Poly::from({}.clone())", self.text),
                 _ => return self,
-            });
+            })
         } else if self.ty == SType::Poly {
             assert!(!self.text.ends_with(".as_num()"));
             assert!(!self.text.ends_with(".as_str()"));
             assert!(!self.text.ends_with(".as_bool()"));
-            return rval(*want, match want {
-                &SType::Number => format!("{}.as_num()", self.text),
-                &SType::Str => format!("{}.as_str()", self.text),
-                &SType::Bool => format!("{}.as_bool()", self.text),
+            rval(*want, match *want {
+                SType::Integer => format!("({}.as_num() as i64)", self.text),
+                SType::Number => format!("{}.as_num()", self.text),
+                SType::Str => format!("{}.as_str()", self.text),
+                SType::Bool => format!("{}.as_bool()", self.text),
                 _ => return self,
             })
-        } else if want == &SType::Str && &self.ty == &SType::Number {
+        } else if want == &SType::Integer && self.ty == SType::Number {
+            // Only happens when run_infer_integers proved it's a whole number.
+            rval(*want, format!("({} as i64)", self.text))
+        } else if self.ty == SType::Integer && !want.is_list() {
+            rval(SType::Number, format!("({} as f64)", self.text)).coerce(want)
+        } else if want == &SType::Str && self.ty == SType::Number {
             // TODO: this is only valid in string concat, otherwise probably an inference bug?
-            return rval(*want, format!("Poly::from({}).as_str()", self.text))
-        } else if want == &SType::Number && &self.ty == &SType::Str {
-            panic!("Poly::from({self:?}).as_num()");
-        } else if want == &SType::ListPoly {
-            return rval(*want, format!("List::<Poly>::from(vec![Poly::from({})])", self.text));
-        } else if self.ty == SType::ListPoly {
+            rval(*want, format!("Poly::from({}).as_str()", self.text))
+        } else if want.is_list() && !self.ty.is_list() {
+            let item = want.list_item().unwrap_or(SType::Poly);
+            rval(*want, format!("List::from(vec![{}])", self.coerce(&item)))
+        } else if self.ty.is_list() && !want.is_list() {
             // TODO: assert one entry
-            return rval(SType::Poly, format!("{}[1.0]", self.text)).coerce(want);
+            list_get(&self.text, "1.0", self.ty).coerce(want)
         } else if *want == SType::Bool && self.text == "(0f64)" { // TODO: HACK
-                return rval(SType::Bool, format!("false"));
+                rval(SType::Bool, "false".to_string())
+        } else if !want.is_list() && !self.ty.is_list() {
+            // Like a value used somewhere that wants a different type (say (1 < 2)). Same conversion as scratch.
+            self.coerce(&SType::Poly).coerce(want)
         } else {
-            panic!("coerce want {:?} but found {self:?}", want);
+            // Both lists (ie. a ListNumber read where a ListStr is expected). Each item goes through the same conversion as a single value.
+            let from = self.ty.list_item().unwrap_or(SType::Poly);
+            let to = want.list_item().unwrap_or(SType::Poly);
+            let item = rval(from, String::from("item.clone()")).coerce(&to);
+            rval(*want, format!("List::from({}.iter().map(|item| {item}).collect::<Vec<_>>())", self.text))
         }
     }
 }
 
+/// Index a list of type list_t. Strings are cloned since you can't move out of the list.
+fn list_get(list: &str, index: impl Display, list_t: SType) -> RustValue {
+    match list_t.list_item().unwrap_or(SType::Poly) {
+        SType::Str => rval(SType::Str, format!("{list}[{index}].clone()")),
+        item => rval(item, format!("{list}[{index}]")),
+    }
+}
+
+fn num_literal(v: f64) -> String {
+    if v.is_nan() {
+        "f64::NAN".to_string()
+    } else if v == f64::INFINITY {
+        "f64::INFINITY".to_string()
+    } else if v == f64::NEG_INFINITY {
+        "f64::NEG_INFINITY".to_string()
+    } else {
+        // Brackets because I'm not sure of precedence for negative literals
+        format!("({v}f64)")
+    }
+}
+
 fn rval(ty: SType, text: impl ToString) -> RustValue {
     RustValue { ty, text: text.to_string() }
 }
@@ -619,17 +848,17 @@ impl RustStmt {
         if !matches!(self, RustStmt::Sync(_) | RustStmt::Empty) && self.is_sync() {
             let mut x = RustStmt::Empty;
             mem::swap(&mut x, self);
-            *self = RustStmt::Sync(x.to_sync().unwrap());
+            *self = RustStmt::Sync(x.into_sync().unwrap());
         }
         if !matches!(other, RustStmt::Sync(_) | RustStmt::Empty) && other.is_sync() {
             let mut x = RustStmt::Empty;
             mem::swap(&mut x, &mut other);
-            other = RustStmt::Sync(x.to_sync().unwrap());
+            other = RustStmt::Sync(x.into_sync().unwrap());
         }
         match self {
             RustStmt::Sync(s) => {
                 if other.is_sync() {
-                    *s += &other.to_sync().unwrap();
+                    *s += &other.into_sync().unwrap();
                     return;
                 }
                 match &mut other {
@@ -666,7 +895,7 @@ impl RustStmt {
                             let last = body.pop().unwrap();
                             match last {
                                 RustStmt::Block(stmts) => {
-                                    body.extend(stmts.into_iter())
+                                    body.extend(stmts)
                                 }
                                 _ => unreachable!()
                             }
@@ -688,14 +917,11 @@ impl RustStmt {
             }
         }
 
-        match other {
-            RustStmt::Block(body) => {
-                for stmt in body.into_iter() {
-                    self.push(stmt);
-                }
-                return;
+        if let RustStmt::Block(body) = other {
+            for stmt in body.into_iter() {
+                self.push(stmt);
             }
-            _ => {}
+            return;
         }
 
         let mut this = RustStmt::Empty;
@@ -706,10 +932,10 @@ impl RustStmt {
     }
 
     /// None if self contains any await points.
-    fn to_sync(self) -> Option<String> {
+    fn into_sync(self) -> Option<String> {
         let sync_block = |stmts: Vec<RustStmt>|
             if stmts.is_empty() { Some(String::from("")) } else {
-                stmts.into_iter().map(Self::to_sync).reduce(
+                stmts.into_iter().map(Self::into_sync).reduce(
                     |a, b| match (a, b) {
                         (Some(mut a), Some(b)) => {
                             a.push_str(&b);
@@ -723,27 +949,28 @@ impl RustStmt {
             RustStmt::Sync(s) => Some(s),
             RustStmt::Block(stmts) => sync_block(stmts),
             RustStmt::IoAction(_) => None,
-            RustStmt::Loop { var_decls, init, body, end_cond, inc_stmt, after_loop, .. } => {
+            RustStmt::Loop { counter, init, body, end_cond, inc_stmt, after_loop, .. } => {
                 // TODO: test that fails if you forget after_loop here
-                match (body.to_sync(), after_loop.to_sync()) {
+                let var_decls = counter.map(|id| format!("let mut i{id} = 0usize; let mut end{id} = 0usize;")).unwrap_or_default();
+                match (body.into_sync(), after_loop.into_sync()) {
                     (Some(body), Some(after)) => Some(format!("{var_decls} {init}\n while !({end_cond}) {{ {inc_stmt} {body} }} {after}")),
                     _ => None
                 }
             }
             RustStmt::If { cond, if_true, if_false } => {
                 // TODO: copy-n-paste
-                if let (Some(if_true), Some(if_false)) = (if_true.clone().to_sync(), if_false.clone().unwrap_or_else(|| Box::new(RustStmt::Sync(String::new()))).to_sync()) {
+                if let (Some(if_true), Some(if_false)) = (if_true.clone().into_sync(), if_false.clone().unwrap_or_else(|| Box::new(RustStmt::Sync(String::new()))).into_sync()) {
                     Some(format!("if {cond} {{\n {if_true} \n}} else {{\n {if_false} \n}}"))
                 } else {
                     None
                 }
             }
-            RustStmt::Empty => Some(format!("")),
+            RustStmt::Empty => Some(String::new()),
         }
     }
 
     fn is_sync(&self) -> bool {
-        let sync_block = |stmts: &Vec<RustStmt>| stmts.is_empty() || stmts.iter().map(Self::is_sync).all(|b| b);
+        let sync_block = |stmts: &Vec<RustStmt>| stmts.is_empty() || stmts.iter().all(Self::is_sync);
 
         match self {
             RustStmt::Sync(_) => true,
@@ -763,7 +990,8 @@ impl RustStmt {
 struct FutMachine {
     // Each is a block that evaluates to an ioaction.
     branches: Vec<FutBranch>,
-    header: String,
+    /// Loop counters that have to live across await points. Become usize fields of the machine's struct.
+    locals: Vec<String>,
 }
 
 #[derive(Clone, Debug)]
@@ -790,17 +1018,17 @@ impl FutMachine {
         let next = self.branches.len();
         match stmt {
             RustStmt::Empty => {
-                self.branches.push(FutBranch::BasicNone(format!(""), next + 1))
+                self.branches.push(FutBranch::BasicNone(String::new(), next + 1))
             }
             RustStmt::Sync(src) => {
-                self.branches.push(FutBranch::BasicNone(format!("{src}"), next + 1))
+                self.branches.push(FutBranch::BasicNone(src.to_string(), next + 1))
             }
             RustStmt::Block(stmts) => {
                 assert!(!stmts.is_empty());
                 let mut stmts = stmts.into_iter();
                 let mut prev = self.push(stmts.next().unwrap());
                 let index_in = prev.first;
-                for stmt in stmts.into_iter() {
+                for stmt in stmts {
                     let span = self.push(stmt);
                     self.chain(prev.last, span.first);
                     prev = span;
@@ -813,8 +1041,11 @@ impl FutMachine {
             RustStmt::IoAction(a) => {
                 self.branches.push(FutBranch::Basic(a, next + 1))
             }
-            RustStmt::Loop { var_decls, init, body, end_cond, inc_stmt, after_loop, .. } => {
-                self.header += &var_decls;
+            RustStmt::Loop { counter, init, body, end_cond, inc_stmt, after_loop, .. } => {
+                if let Some(id) = counter {
+                    self.locals.push(format!("i{id}"));
+                    self.locals.push(format!("end{id}"));
+                }
                 self.push(RustStmt::Sync(init));
                 let branch_at = self.branches.len();
                 self.branches.push(FutBranch::BackPatch);
@@ -894,9 +1125,10 @@ impl FutMachine {
         }
     }
 
-    fn to_src(self, name: &str, needs_alloc: bool) -> String {
+    /// The body of Machine::resume. Returns from a closure so the fields can be put back after.
+    fn into_src(self, owner: &str) -> String {
         if self.branches.len() == 1 {
-            return self.to_src_single(name, needs_alloc);
+            return self.into_src_single(owner);
         }
 
         let last= self.branches.len() + 1;
@@ -917,11 +1149,8 @@ impl FutMachine {
             branches += &format!("{0} => {1},\n", i+1, body);
         }
 
-        let f = if needs_alloc { "fut_a" } else { "fut" };
-        // Note: header is outside the closure so it only runs once.
-        format!(r#"{header}
-        {f}("{name}", move |ctx, this, s| {{
-            let this: &mut Self = ctx.trusted_cast(this);
+        format!(r#"
+            let this: &mut {owner} = ctx.trusted_cast(this);
             let mut s: u16 = s.into();
             loop {{
                  match s {{
@@ -930,12 +1159,11 @@ impl FutMachine {
                     _ => unreachable!(),
                 }}
             }}
-           unreachable!()
-        }})"#, header=self.header)
+           unreachable!()"#)
     }
 
     // TODO: if function calls had the ctx, this could fully inline the FutMachine
-    fn to_src_single(self, name: &str, needs_alloc: bool) -> String {
+    fn into_src_single(self, owner: &str) -> String {
         assert_eq!(self.branches.len(), 1);
         let b = self.branches.into_iter().next().unwrap();
         let body = match b {
@@ -944,14 +1172,10 @@ impl FutMachine {
             FutBranch::Branch { .. } | FutBranch::BackPatch => unreachable!(),
         };
 
-        let f = if needs_alloc { "fut_a" } else { "fut" };
-        // Note: header is outside the closure so it only runs once.
-        format!(r#"{header}
-        {f}("{name}", move |ctx, this, s| {{
-            let this: &mut Self = ctx.trusted_cast(this);
+        format!(r#"
+            let this: &mut {owner} = ctx.trusted_cast(this);
             debug_assert_eq!(u16::from(s), 1);
-            {body}
-        }})"#, header=self.header)
+            {body}"#)
     }
 }
 
@@ -963,20 +1187,18 @@ impl From<RustStmt> for FutMachine {
     }
 }
 
-#[derive(Debug)]
-struct IoAction(String, usize);
-
-
-impl Display for IoAction {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        f.write_str(&self.0)
-    }
+/// Integer is just an optimisation of Number so they can be compared directly.
+fn widen_int(t: SType) -> SType {
+    if t == SType::Integer { SType::Number } else { t }
 }
 
 fn type_name(t: SType) -> &'static str {
     match t {
+        SType::Integer => "i64",
         SType::Number => "f64",
-        SType::ListPoly => "List<Poly>",
+        SType::ListUnknown | SType::ListPoly => "List<Poly>",
+        SType::ListNumber => "List<f64>",
+        SType::ListStr => "List<Str>",
         SType::Bool => "bool",
         SType::Str => "Str",
         SType::Poly => "Poly"
@@ -0,0 +1,27 @@
+//! Runtime functions that directly implement a block.
+
+use crate::ast::SType;
+
+pub struct Builtin {
+    pub opcode: &'static str,
+    pub args: &'static [SType],
+    /// None for statements.
+    pub ret: Option<SType>,
+}
+
+macro_rules! registry {
+    (@ret) => { None };
+    (@ret $t:ident) => { Some(SType::$t) };
+    ($($name:ident($($arg:ident: $t:ident),*) $(-> $ret:ident)?;)*) => {
+        /// Shared with the runtime which checks the signatures match its FrameCtx methods.
+        pub const BUILTINS: &[Builtin] = &[$(
+            Builtin { opcode: stringify!($name), args: &[$(SType::$t),*], ret: registry!(@ret $($ret)?) },
+        )*];
+    };
+}
+
+runtime::builtin_registry!(registry);
+
+pub fn builtin(opcode: &str) -> Option<&'static Builtin> {
+    BUILTINS.iter().find(|b| b.opcode == opcode)
+}
@@ -9,6 +9,8 @@ use zip::ZipArchive;
 use crate::{AssetPackaging, Target};
 use crate::ast::Project;
 use crate::backend::rust::{emit_rust};
+use crate::coverage::coverage;
+use crate::diagnostic::Severity;
 use crate::scratch_schema::parse;
 
 // the function that does it from an iter is a pain cause they all have to be the same type.
@@ -32,9 +34,9 @@ pub fn run(opts: Cli) -> anyhow::Result<()> {
     }
 
     assert_eq!(opts.assets, AssetPackaging::Embed);
-    let (project, name) = if opts.input.starts_with("http") {
+    let (project, name, sb3) = if opts.input.starts_with("http") {
         assert!(opts.input.contains("scratch.mit.edu/projects") || opts.input.contains("turbowarp.org"));
-        let id = opts.input.split("?").nth(0).unwrap().trim_end_matches("/").split("/").last().unwrap().trim();
+        let id = opts.input.split("?").next().unwrap().trim_end_matches("/").split("/").last().unwrap().trim();
         let id_num = id.parse::<u64>()?;
 
         let res = ureq::get(&format!("https://api.scratch.mit.edu/projects/{id}"))
@@ -49,17 +51,42 @@ pub fn run(opts: Cli) -> anyhow::Result<()> {
             .call()?.into_string()?;
 
         let project = parse(raw.as_str())?;
-
-        (project, format!("s{id}"))
+        (project, format!("s{id}"), None)
     } else if opts.input.ends_with(".sb3") {
         let bytes = fs::read(PathBuf::from(&opts.input))?;
         let mut zip = ZipArchive::new(Cursor::new(bytes))?;
         let proj = zip.by_name("project.json")?;
         let raw = read_to_string(proj)?;
-        create_dir_all(&opts.outdir)?;
-        fs::write(path!(opts.outdir, "project.json"), raw.as_str())?;
         let project = parse(raw.as_str())?;
         let name = PathBuf::from(&opts.input).file_name().unwrap().to_string_lossy().replace(['.'], "_");
+        (project, name, Some((zip, raw)))
+    } else {
+        panic!("Unsupported input. Expected url or local .sb3 file path.");
+    };
+
+    if opts.coverage {
+        print!("{}", coverage(project));
+        return Ok(());
+    }
+
+    let project = Project::try_from(project)?;
+    for warning in project.diagnostics.iter().filter(|d| d.severity != Severity::Note) {
+        eprintln!("{warning}");
+    }
+
+    #[cfg(feature = "interp")]
+    if opts.interpret {
+        use runtime::RenderBackend;
+        use crate::backend::interp::{self, Console, Program};
+        // The console doesn't draw so doesn't need the costumes.
+        interp::load(project, vec![]);
+        <Console as RenderBackend<Program>>::run();
+        return Ok(());
+    }
+
+    if let Some((mut zip, raw)) = sb3 {
+        create_dir_all(&opts.outdir)?;
+        fs::write(path!(opts.outdir, "project.json"), raw.as_str())?;
 
         // TODO: if Assets::fetch, do a dry run to make sure scratch has the referenced things (it might be a local sb3 never shared)
         // TODO: Assets::fetch option to change base url
@@ -76,18 +103,15 @@ pub fn run(opts: Cli) -> anyhow::Result<()> {
                 file.read_to_end(&mut buf).unwrap();
                 fs::write(path!(assets_path, c.md5ext.clone()), buf).unwrap();
             });
-
-        (project, name)
-    } else {
-        panic!("Unsupported input. Expected url or local .sb3 file path.");
-    };
-
-    let project: Project = project.into();
+    }
 
     if opts.deny_async {
         assert!(!project.any_async, "Made async calls but --deny-async");
     }
-    let result = emit_rust(&project, opts.render, opts.assets);
+    let (result, diagnostics) = emit_rust(&project, opts.render, opts.assets);
+    for warning in &diagnostics {
+        eprintln!("{warning}");
+    }
 
     let src_path = path!(opts.outdir, "src");
     create_dir_all(&src_path)?;
@@ -95,7 +119,20 @@ pub fn run(opts: Cli) -> anyhow::Result<()> {
 
     fs::write(path!(opts.outdir, ".gitignore"), "target\nproject.json\n.DS_Store\n")?;
 
-    let cargotoml = template!(opts, "data/cargo_toml", name=&name, backend=opts.render.code_name(), maybe_inspect=if opts.inspect { "\"inspect\", "} else { "" } );
+    let mut default_features = String::new();
+    if opts.inspect {
+        default_features += "\"inspect\", ";
+    }
+    if opts.save_states {
+        default_features += "\"save\", ";
+    }
+    if !project.cloud_vars.is_empty() {
+        default_features += "\"cloud\", ";
+    }
+    if opts.render == Target::Trace {
+        default_features += "\"vars\", ";  // The trace lists the final value of every variable.
+    }
+    let cargotoml = template!(opts, "data/cargo_toml", name=&name, backend=opts.render.code_name(), default_features=default_features);
     fs::write(path!(opts.outdir, "Cargo.toml"), cargotoml)?;
 
     // TODO: output this in the web demo too
@@ -119,7 +156,7 @@ pub fn run(opts: Cli) -> anyhow::Result<()> {
     let warning = template!(opts, "data/macroquad_warning", );
     if opts.web {
         let mut cmd = Command::new("cargo");
-        cmd.args(&["build", "--release", "--target", "wasm32-unknown-unknown"]);
+        cmd.args(["build", "--release", "--target", "wasm32-unknown-unknown"]);
         assert!(cmd.current_dir(&opts.outdir).status()?.success());
 
         let target_folder = {
@@ -144,7 +181,7 @@ pub fn run(opts: Cli) -> anyhow::Result<()> {
         let mut cmd = Command::new("wasm-bindgen");
         // TODO: helper for run external command and offering to install if not available
         // TODO: see if the other cli args in the help menu can make my patch less egregious
-        cmd.arg(wasm).args(&["--target", "web", "--out-dir"]).arg(&web_folder).arg("--no-typescript");
+        cmd.arg(wasm).args(["--target", "web", "--out-dir"]).arg(&web_folder).arg("--no-typescript");
         let bindjs = path!(web_folder, format!("{name}.js"));
         assert!(cmd.status()?.success());
 
@@ -247,6 +284,10 @@ pub struct Cli {
     #[arg(long)]
     pub inspect: bool,
 
+    /// Let the executable write and load save states (--save <path> and --load <path>).
+    #[arg(long)]
+    pub save_states: bool,
+
     /// What to use as the User-Agent http header when the compiler calls the scratch api.
     #[arg(long, default_value = "github/LukeGrahamLandry/hctarcs")]
     pub user_agent: String,
@@ -297,6 +338,15 @@ pub struct Cli {
     #[arg(long)]
     pub debug: bool,
 
+    /// Instead of compiling, list which blocks the project uses and how well they're supported, then exit.
+    #[arg(long)]
+    pub coverage: bool,
+
+    /// Instead of compiling, run the project with the AST interpreter. There's no window. Say blocks print to stdout and ask reads a line from stdin.
+    #[cfg(feature = "interp")]
+    #[arg(long)]
+    pub interpret: bool,
+
 
     // TODO: --sc1 (and walk up file tree to find assets folder to put cwd)
 }
@@ -305,7 +355,7 @@ impl Cli {
     fn get_template_path(&self, name: &str) -> Option<&str> {
         // TODO: HACK until i figure out abs paths. also should use pathbuf function?
         let name = name.split("/").last().unwrap();  // lookup key is a magic id, not a path
-        self.template.iter().map(|templates| {  // TODO: untested
+        self.template.iter().flat_map(|templates| {  // TODO: untested
             templates.iter().find_map(|arg| {
                 let mut parts = arg.split('=');
                 let key = parts.next().expect("Override key.").trim();
@@ -319,7 +369,7 @@ impl Cli {
                     None
                 }
             })
-        }).flatten().next()
+        }).next()
     }
 }
 
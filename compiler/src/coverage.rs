@@ -0,0 +1,140 @@
+//! Which blocks a project uses and how well they're supported, without generating any code.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
+use crate::ast::Project;
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::scratch_schema::{RawSprite, ScratchProject};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Support {
+    Full,
+    /// Compiles and runs but some of the behaviour is missing.
+    Partial,
+    /// Does nothing when it runs or doesn't compile at all.
+    Unsupported,
+}
+
+pub struct SpriteCoverage {
+    pub name: String,
+    /// How many blocks with each opcode. The same opcode can show up in multiple groups (ie. cloning yourself vs another sprite).
+    pub opcodes: BTreeMap<(Support, String), usize>,
+    /// Blocks that aren't under a hat so never run.
+    pub unused: usize,
+}
+
+pub struct Coverage {
+    pub sprites: Vec<SpriteCoverage>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+pub fn coverage(project: ScratchProject) -> Coverage {
+    // Have to look at the blocks before the project is consumed.
+    let sprites: Vec<_> = project.targets.iter().map(|target| {
+        let used: Vec<_> = target.blocks.iter()
+            .filter(|(id, _)| is_used(target, id))
+            .map(|(id, block)| (id.clone(), block.opcode.clone()))
+            .collect();
+        (target.name.clone(), used, target.blocks.len())
+    }).collect();
+
+    let diagnostics = match Project::try_from(project) {
+        Ok(project) => project.diagnostics,
+        Err(e) => e.diagnostics,
+    };
+
+    // The worst problem reported for each block.
+    let mut severity: HashMap<(&str, &str), Severity> = HashMap::new();
+    for d in &diagnostics {
+        if let (Some(sprite), Some(id)) = (&d.sprite, &d.block_id) {
+            let s = severity.entry((sprite, id)).or_insert(d.severity);
+            *s = (*s).max(d.severity);
+        }
+    }
+
+    let sprites = sprites.into_iter().map(|(name, used, total)| {
+        let mut opcodes = BTreeMap::new();
+        for (id, opcode) in &used {
+            let support = match severity.get(&(name.as_str(), id.as_str())) {
+                None => Support::Full,
+                Some(Severity::Note) => Support::Partial,
+                Some(Severity::Warning | Severity::Error) => Support::Unsupported,
+            };
+            *opcodes.entry((support, opcode.clone())).or_insert(0) += 1;
+        }
+        SpriteCoverage { unused: total - used.len(), name, opcodes }
+    }).collect();
+
+    Coverage { sprites, diagnostics }
+}
+
+/// Is the block part of a script or procedure?
+fn is_used<'a>(target: &'a RawSprite, mut id: &'a str) -> bool {
+    loop {
+        let Some(block) = target.blocks.get(id) else {
+            return false;
+        };
+        match &block.parent {
+            Some(parent) => id = parent,
+            None => return block.opcode.starts_with("event_when") || block.opcode == "procedures_definition",
+        }
+    }
+}
+
+impl SpriteCoverage {
+    pub fn count(&self, support: Support) -> usize {
+        self.opcodes.iter().filter(|((s, _), _)| *s == support).map(|(_, n)| n).sum()
+    }
+}
+
+impl Coverage {
+    pub fn count(&self, support: Support) -> usize {
+        self.sprites.iter().map(|s| s.count(support)).sum()
+    }
+
+    pub fn worst(&self) -> Option<Severity> {
+        self.diagnostics.iter().map(|d| d.severity).max()
+    }
+}
+
+impl Display for Support {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.pad(match self {
+            Support::Full => "supported",
+            Support::Partial => "partial",
+            Support::Unsupported => "unsupported",
+        })
+    }
+}
+
+impl Display for Coverage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let counts = |f: &mut Formatter<'_>, count: &dyn Fn(Support) -> usize| {
+            write!(f, "{} supported, {} partial, {} unsupported", count(Support::Full), count(Support::Partial), count(Support::Unsupported))
+        };
+        for sprite in &self.sprites {
+            write!(f, "Sprite \"{}\": ", sprite.name)?;
+            counts(f, &|s| sprite.count(s))?;
+            writeln!(f, ", {} unused", sprite.unused)?;
+            // Worst first so the interesting ones are at the top.
+            for ((support, opcode), n) in sprite.opcodes.iter().rev() {
+                writeln!(f, "    {support:<12} {opcode} x{n}")?;
+            }
+        }
+
+        write!(f, "Total: ")?;
+        counts(f, &|s| self.count(s))?;
+        writeln!(f)?;
+        for d in self.diagnostics.iter().filter(|d| d.severity != Severity::Note) {
+            writeln!(f, "{d}")?;
+        }
+
+        write!(f, "Verdict: ")?;
+        match self.worst() {
+            None => writeln!(f, "Fully supported."),
+            Some(Severity::Note) => writeln!(f, "Compiles and runs but some blocks are only partially supported."),
+            Some(Severity::Warning) => writeln!(f, "Compiles but unsupported blocks do nothing."),
+            Some(Severity::Error) => writeln!(f, "Will not compile."),
+        }
+    }
+}
@@ -0,0 +1,84 @@
+//! Problems found while compiling a project, reported to the user instead of panicking.
+
+use std::fmt::{Display, Formatter};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Compiles and runs but some of the block's behaviour is missing (ie. data_hidelist does nothing).
+    Note,
+    /// The output still compiles but won't behave like scratch (ie. an unknown opcode does nothing).
+    Warning,
+    /// No useful output can be generated.
+    Error,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// The scratch name, not the mangled one. None if it's about the whole project.
+    pub sprite: Option<String>,
+    pub block_id: Option<String>,
+    pub opcode: Option<String>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+/// Where a diagnostic points. Passes that only know some of it leave the rest None.
+#[derive(Debug, Clone, Default)]
+pub struct Location {
+    pub sprite: Option<String>,
+    pub block_id: Option<String>,
+    pub opcode: Option<String>,
+}
+
+impl Location {
+    pub fn diagnostic(&self, severity: Severity, message: String) -> Diagnostic {
+        let Location { sprite, block_id, opcode } = self.clone();
+        Diagnostic { severity, sprite, block_id, opcode, message }
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.severity {
+            Severity::Note => write!(f, "note")?,
+            Severity::Warning => write!(f, "warning")?,
+            Severity::Error => write!(f, "error")?,
+        }
+        if let Some(sprite) = &self.sprite {
+            write!(f, " in sprite \"{sprite}\"")?;
+        }
+        if let Some(opcode) = &self.opcode {
+            write!(f, " at {opcode}")?;
+        }
+        if let Some(id) = &self.block_id {
+            write!(f, " (block {id})")?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// Every problem found in a project that could not be compiled. Includes the warnings too.
+#[derive(Debug, Clone)]
+pub struct CompileError {
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Display for CompileError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let errors = self.diagnostics.iter().filter(|d| d.is_error()).count();
+        writeln!(f, "Failed to compile project ({errors} errors).")?;
+        for d in &self.diagnostics {
+            writeln!(f, "{d}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for CompileError {}
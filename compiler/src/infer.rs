@@ -1,22 +1,30 @@
-use crate::ast::{Expr, Project, Stmt, SType};
-use crate::parse::{expect_type, infer_type};
+use crate::ast::{Expr, Project, Sprite, Stmt, SType, VarId};
+use crate::diagnostic::Location;
+use crate::parse::{expect_type, infer_type, is_int};
 
-// TODO: need to infer list element types as well (tried unsafe unwrap for quick sort benchmark and it was faster)
 // TODO: write down why this will always terminate
 pub fn run_infer(project: &mut Project) {
     // This feels sad and slow but it seems to not that big a difference and does improve inference coverage.
     let mut count = 0;
     let mut last = false;
+    // Once everything else has settled, list items that are still unknown (answer, untyped arguments, ...) could be anything.
+    let mut widen_unknown = false;
     loop {
         let mut infer = Infer {
             project,
             dirty: false,
             current_fn: None,
+            widen_unknown,
         };
         infer.run();
         if !infer.dirty {
             if last {
-                break;
+                if widen_unknown {
+                    break;
+                }
+                widen_unknown = true;
+                last = false;
+                continue;
             }
             last = true;
 
@@ -30,16 +38,79 @@ pub fn run_infer(project: &mut Project) {
     }
 }
 
+/// Narrows Number variables to Integer when every write to them is a whole number (see parse::is_int).
+/// Starts by assuming they all are and demotes any with a write that might not be until nothing changes,
+/// so counters that only depend on each other still work out.
+pub fn run_infer_integers(project: &mut Project) {
+    // None is a RepeatTimesCapture counter which is always an integer.
+    let mut writes: Vec<(VarId, Option<Expr>)> = vec![];
+    for target in &project.targets {
+        for (v, value) in target.fields.iter().zip(target.field_defaults.iter()) {
+            writes.push((*v, Some(value.clone().unwrap_or(Expr::Empty))));
+        }
+        for script in &target.scripts {
+            collect_writes(target, &script.body, &mut writes);
+        }
+        for proc in &target.procedures {
+            collect_writes(target, &proc.body, &mut writes);
+        }
+    }
+
+    // Cloud variables are left alone since other programs might write fractions to them.
+    for (i, t) in project.expected_types.iter_mut().enumerate() {
+        if *t == Some(SType::Number) && !project.cloud_vars.contains(&VarId(i)) {
+            *t = Some(SType::Integer);
+        }
+    }
+
+    loop {
+        let mut changed = false;
+        for (v, value) in &writes {
+            let ok = value.as_ref().is_none_or(|e| is_int(project, e));
+            if !ok && project.expected_types[v.0] == Some(SType::Integer) {
+                project.expected_types[v.0] = Some(SType::Number);
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+}
+
+fn collect_writes(target: &Sprite, body: &[Stmt], writes: &mut Vec<(VarId, Option<Expr>)>) {
+    for stmt in body {
+        match stmt {
+            Stmt::SetField(v, e) | Stmt::SetGlobal(v, e) => writes.push((*v, Some(e.clone()))),
+            Stmt::RepeatTimesCapture(_, _, v, _) => writes.push((*v, None)),
+            Stmt::CallCustom(name, args) => {
+                let proc = target.lookup_proc(name).unwrap();
+                for (v, e) in proc.args.iter().zip(args.iter()) {
+                    writes.push((*v, Some(e.clone())));
+                }
+            }
+            _ => {}
+        }
+        for body in stmt.bodies() {
+            collect_writes(target, body, writes);
+        }
+    }
+}
+
 struct Infer<'a> {
     project: &'a mut Project,
     dirty: bool,
     // None if in a script since those are always async. In proc, (sprite_index, func_index)
-    current_fn: Option<(usize, usize)>
+    current_fn: Option<(usize, usize)>,
+    /// Writing an item of unknown type makes the list ListPoly instead of leaving it for a later round.
+    widen_unknown: bool,
 }
 
 impl<'a> Infer<'a> {
     fn run(&mut self) {
         for i in 0..self.project.targets.len() {
+            // Blocks don't keep their ids this far so type changes can only say which sprite.
+            self.project.at = Location { sprite: Some(self.project.targets[i].scratch_name.clone()), ..Location::default() };
             for j in 0..self.project.targets[i].procedures.len() {
                 let proc = &self.project.targets[i].procedures[j];
                 let block = proc.body.clone();
@@ -50,6 +121,7 @@ impl<'a> Infer<'a> {
         }
 
         for i in 0..self.project.targets.len() {
+            self.project.at = Location { sprite: Some(self.project.targets[i].scratch_name.clone()), ..Location::default() };
             for j in 0..self.project.targets[i].scripts.len() {
                 let block = self.project.targets[i].scripts[j].body.clone();
                 self.current_fn = None;
@@ -75,6 +147,13 @@ impl<'a> Infer<'a> {
         }
     }
 
+    // The element type of a list is the union of everything written to it.
+    fn infer_list_item(&mut self, list: VarId, item: &Expr) {
+        let unknown = if self.widen_unknown { SType::ListPoly } else { SType::ListUnknown };
+        let t = infer_type(self.project, item).map_or(unknown, SType::list_of);
+        self.dirty |= self.project.expect_type(list, t);
+    }
+
     fn infer_block(&mut self, s: Vec<Stmt>) {
         for s in s {
             self.infer_stmt(s)
@@ -109,22 +188,22 @@ impl<'a> Infer<'a> {
                 }
             }
             Stmt::ListSet(_, v, i, val) => {
-                self.dirty |= self.project.expect_type(v, SType::ListPoly);
+                self.infer_list_item(v, &val);
                 self.infer_expr(i);
                 self.infer_expr(val);
             }
             Stmt::ListPush(_, v, e)  => {
-                self.dirty |= self.project.expect_type(v, SType::ListPoly);
+                self.infer_list_item(v, &e);
                 self.infer_expr(e);
             }
             Stmt::ListClear(_, v) => {
-                self.dirty |= self.project.expect_type(v, SType::ListPoly);
+                self.dirty |= self.project.expect_type(v, SType::ListUnknown);
             }
             Stmt::ListRemoveIndex(_, v, i) => {
-                self.dirty |= self.project.expect_type(v, SType::ListPoly);
+                self.dirty |= self.project.expect_type(v, SType::ListUnknown);
                 self.infer_expr(i);
             }
-            Stmt::BuiltinRuntimeCall(_, args) => {
+            Stmt::BuiltinRuntimeCall(_, args, _) => {
                 for a in args {
                     self.infer_expr(a);
                 }
@@ -138,7 +217,9 @@ impl<'a> Infer<'a> {
                 }
             }
             Stmt::UnknownOpcode(_) => {}
-            Stmt::CloneMyself => {}
+            Stmt::CloneMyself(_) => {}
+            // Only parsed outside run without screen refresh procedures, which loop without yielding.
+            Stmt::LoopYield => self.mark_async(),
             Stmt::WaitSeconds(e) => {
                 self.infer_expr(e);
                 self.mark_async();
@@ -151,7 +232,7 @@ impl<'a> Infer<'a> {
                 // TODO: comment out when done testing
                 // self.mark_async();
             }
-            Stmt::BroadcastWait(_) | Stmt::Exit => {
+            Stmt::BroadcastWait(_) | Stmt::Exit | Stmt::StopOtherScripts => {
                 self.mark_async();
             }
             Stmt::AskAndWait(e) => {
@@ -27,13 +27,20 @@ pub mod scratch_schema;
 pub mod ast;
 pub mod parse;
 pub mod backend;
+pub mod names;
+pub mod diagnostic;
+pub mod coverage;
+pub mod builtins;
 
 #[cfg(feature = "cli")]
 pub mod cli;
 mod infer;
+mod opt;
 
+/// C api for the wasm build. It only compiles, running the result still needs the generated crate built (see backend::interp).
 pub mod wasm_interface {
     use std::alloc::{alloc, Layout};
+    use std::cell::RefCell;
     use std::ffi::{c_char, CStr, CString};
     use std::ptr::slice_from_raw_parts;
     use crate::ast::Project;
@@ -41,13 +48,39 @@ pub mod wasm_interface {
     use crate::scratch_schema::parse;
     use crate::{AssetPackaging, Target};
 
+    thread_local! {
+        static DIAGNOSTICS: RefCell<String> = const { RefCell::new(String::new()) };
+    }
+
     /// len does NOT include null terminator.
+    /// Returns null if the project could not be compiled. Call get_diagnostics to find out why.
+    /// # Safety
+    /// project_json must point to len bytes of utf8 (from alloc_str).
     #[no_mangle]
     pub unsafe extern "C" fn compile_sb3(project_json: *const u8, len: usize) -> *const c_char {
         let s = &*slice_from_raw_parts(project_json, len);
-        let project: Project = parse(std::str::from_utf8(s).unwrap()).unwrap().into();
-        let src = emit_rust(&project, Target::Macroquad, AssetPackaging::Fetch);
-        CString::new(src).unwrap().into_raw()
+        let project = parse(std::str::from_utf8(s).unwrap())
+            .map_err(|e| format!("Invalid project.json: {e}"))
+            .and_then(|project| Project::try_from(project).map_err(|e| e.to_string()));
+        match project {
+            Ok(project) => {
+                let (src, diagnostics) = emit_rust(&project, Target::Macroquad, AssetPackaging::Fetch);
+                let warnings = project.diagnostics.iter().chain(&diagnostics).map(|d| format!("{d}\n")).collect();
+                DIAGNOSTICS.set(warnings);
+                CString::new(src).unwrap().into_raw()
+            }
+            Err(e) => {
+                DIAGNOSTICS.set(e);
+                std::ptr::null()
+            }
+        }
+    }
+
+    /// The errors or warnings from the last call to compile_sb3. Empty string if there were none.
+    #[no_mangle]
+    pub extern "C" fn get_diagnostics() -> *const c_char {
+        // Messages quote names from the project which could contain a NUL.
+        CString::new(DIAGNOSTICS.take().replace('\0', "\\0")).unwrap().into_raw()
     }
 
     #[no_mangle]
@@ -56,17 +89,23 @@ pub mod wasm_interface {
     }
 
     /// len DOES include null terminator
+    /// # Safety
+    /// Free the result with drop_c_str (after writing a null terminator).
     #[no_mangle]
     pub unsafe extern "C" fn alloc_str(len: usize) -> *mut u8 {
         alloc(Layout::array::<u8>(len).unwrap())
     }
 
+    /// # Safety
+    /// ptr must have been returned by this module and not already dropped.
     #[no_mangle]
     pub unsafe extern "C" fn drop_c_str(ptr: *mut c_char) {
         let _ = CString::from_raw(ptr);
     }
 
     /// result does NOT include null terminator.
+    /// # Safety
+    /// ptr must be a valid null terminated string.
     #[no_mangle]
     pub unsafe extern "C" fn c_str_len(ptr: *mut c_char) -> usize {
         CStr::from_ptr(ptr).to_bytes().len()
@@ -91,6 +130,12 @@ pub enum Target {
     Softbuffer,
     #[default]
     Macroquad,
+    /// No window. Writes what the program did to trace.txt for tests.
+    Trace,
+    /// No window or gpu. Draws on the cpu and saves frame.png (see --frames and --every-frame on the exe).
+    Headless,
+    /// No window. Say prints to stdout and ask reads a line from stdin. For text programs.
+    Console,
 }
 
 impl Target {
@@ -99,6 +144,9 @@ impl Target {
             Target::Notan => "notan",
             Target::Softbuffer => "softbuffer",
             Target::Macroquad => "macroquad",
+            Target::Trace => "trace",
+            Target::Headless => "headless",
+            Target::Console => "console",
         }
     }
 }
@@ -4,7 +4,10 @@ fn main() {
     {
         use clap::Parser;
         use compiler::cli::{Cli, run};
-        run(Cli::parse()).unwrap();
+        if let Err(e) = run(Cli::parse()) {
+            eprintln!("{e:?}");
+            std::process::exit(1);
+        }
     }
 
     // Don't bother pulling in dependencies if only want to use the library part.
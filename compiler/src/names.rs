@@ -0,0 +1,60 @@
+//! Turning scratch names into unique Rust identifiers.
+
+use std::collections::HashSet;
+use serde::{Deserialize, Serialize};
+
+/// Everything (sprites, variables, arguments, procedures, messages) shares one namespace.
+/// That's more than strictly needed but means nothing can shadow anything else.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Names {
+    used: HashSet<String>,
+}
+
+impl Default for Names {
+    fn default() -> Self {
+        let mut used: HashSet<String> = RESERVED.iter().map(|s| s.to_string()).collect();
+        used.extend(TEMPLATE_NAMES.iter().map(|s| s.to_string()));
+        Names { used }
+    }
+}
+
+impl Names {
+    /// A valid identifier that hasn't been returned before. Tries to keep it readable.
+    pub fn mangle(&mut self, name: &str) -> String {
+        let mut base: String = name.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+        if base.is_empty() || base.starts_with(|c: char| c.is_ascii_digit()) || base == "_" {
+            base.insert(0, 'v');
+        }
+
+        let mut ident = base.clone();
+        let mut i = 1;
+        while is_loop_var(&ident) || !self.used.insert(ident.clone()) {
+            i += 1;
+            ident = format!("{base}_{i}");
+        }
+        ident
+    }
+}
+
+/// The emitter declares locals called i1, end1, etc. for loops.
+fn is_loop_var(ident: &str) -> bool {
+    let digits = ident.strip_prefix('i').or(ident.strip_prefix("end"));
+    digits.is_some_and(|d| !d.is_empty() && d.bytes().all(|b| b.is_ascii_digit()))
+}
+
+const RESERVED: &[&str] = &[
+    "as", "break", "const", "continue", "crate", "else", "enum", "extern", "false", "fn", "for", "if", "impl", "in",
+    "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return", "self", "Self", "static", "struct", "super",
+    "trait", "true", "type", "unsafe", "use", "where", "while", "async", "await", "dyn", "abstract", "become", "box",
+    "do", "final", "macro", "override", "priv", "typeof", "unsized", "virtual", "yield", "try", "gen", "union",
+];
+
+/// Names the templates, emitter, or runtime (glob imported) use that a user defined name would shadow.
+const TEMPLATE_NAMES: &[&str] = &[
+    "ctx", "this", "s", "index", "item", "__cond", "__res", "main", "msg_of", "state", "fut", "fut_a",
+    "dyn_rand", "rand_int", "rand_float", "str_to_num_list", "str_to_poly_list", "runtime", "std",
+    "Stage", "Backend", "Ctx", "Msg", "InvalidComputedMessage", "Trigger", "IoAction", "Str", "Poly", "List",
+    "Sprite", "ScratchProgram", "FrameCtx", "RenderBackend", "ScratchAsset", "Any", "V",
+    "Machine", "FutRes", "Save", "SaveWriter", "SaveReader", "Config", "BackendImpl", "VarBorrow", "VarBorrowMut",
+    "Default", "Clone", "Copy", "Debug", "PartialEq", "Eq", "Hash", "Box", "Vec", "Option", "Some", "None", "String",
+];
@@ -0,0 +1,151 @@
+//! Simplifying the AST before type inference.
+
+use std::collections::HashMap;
+use runtime::costume_index;
+use crate::ast::{BinOp, Expr, Lit, Project, Stmt, UnOp, VarId};
+
+/// Folds constant expressions and resolves constant costume and message names to ids.
+pub fn optimize(project: &mut Project) {
+    let mut opt = Opt { messages: &project.triggers_by_name, first_costume: 0, costumes: vec![] };
+    for target in &mut project.targets {
+        // Same order as get_costumes.
+        opt.first_costume += opt.costumes.len();
+        opt.costumes = target.costumes.iter().map(|c| c.name.clone()).collect();
+        for script in &mut target.scripts {
+            opt.block(&mut script.body);
+        }
+        for proc in &mut target.procedures {
+            opt.block(&mut proc.body);
+        }
+    }
+}
+
+struct Opt<'a> {
+    messages: &'a HashMap<String, VarId>,
+    /// Id of the current target's first costume.
+    first_costume: usize,
+    /// Names of the current target's costumes.
+    costumes: Vec<String>,
+}
+
+impl<'a> Opt<'a> {
+    /// Resolved the same way the runtime does for a name that isn't constant.
+    fn costume_id(&self, costume: &Lit) -> Option<usize> {
+        let names: Vec<&str> = self.costumes.iter().map(String::as_str).collect();
+        costume_index(&names, &costume.to_string()).map(|index| self.first_costume + index)
+    }
+
+    fn block(&self, body: &mut [Stmt]) {
+        for s in body {
+            self.stmt(s);
+        }
+    }
+
+    fn stmt(&self, stmt: &mut Stmt) {
+        match stmt {
+            Stmt::RepeatTimes(e, s) |
+            Stmt::If(e, s) |
+            Stmt::RepeatUntil(e, s) |
+            Stmt::RepeatTimesCapture(e, s, _, _) => {
+                fold(e);
+                self.block(s);
+            }
+            Stmt::IfElse(e, s, s1) => {
+                fold(e);
+                self.block(s);
+                self.block(s1);
+            }
+            Stmt::SetField(_, e) |
+            Stmt::SetGlobal(_, e) |
+            Stmt::ListPush(_, _, e) |
+            Stmt::ListRemoveIndex(_, _, e) |
+            Stmt::WaitSeconds(e) |
+            Stmt::AskAndWait(e) => fold(e),
+            Stmt::ListSet(_, _, i, e) => {
+                fold(i);
+                fold(e);
+            }
+            Stmt::CallCustom(_, args) => args.iter_mut().for_each(fold),
+            Stmt::BuiltinRuntimeCall(name, args, id) => {
+                args.iter_mut().for_each(fold);
+                if name == "looks_switchcostumeto" {
+                    if let [Expr::Literal(costume)] = args.as_slice() {
+                        *stmt = match self.costume_id(costume) {
+                            Some(costume) => Stmt::BuiltinRuntimeCall("looks_switchcostumeto_id".to_string(), vec![Expr::Literal(Lit::Num(costume as f64))], id.clone()),
+                            None => Stmt::Empty,  // The runtime silently ignores unknown names too.
+                        };
+                    }
+                }
+            }
+            Stmt::BroadcastWait(e) => {
+                fold(e);
+                if let Expr::Literal(name) = e {
+                    *e = Expr::Message(self.messages.get(&name.to_string()).copied());
+                }
+            }
+            Stmt::StopScript | Stmt::Exit | Stmt::StopOtherScripts | Stmt::CloneMyself(_) | Stmt::LoopYield | Stmt::ListClear(_, _) | Stmt::UnknownOpcode(_) | Stmt::Empty => {}
+        }
+    }
+}
+
+fn fold(e: &mut Expr) {
+    let folded = match e {
+        Expr::Bin(op, lhs, rhs) => {
+            fold(lhs);
+            fold(rhs);
+            match (lhs.as_ref(), rhs.as_ref()) {
+                (Expr::Literal(a), Expr::Literal(b)) => fold_bin(op, a, b),
+                _ => None,
+            }
+        }
+        Expr::Un(op, v) => {
+            fold(v);
+            match v.as_ref() {
+                Expr::Literal(a) => fold_un(op, a),
+                _ => None,
+            }
+        }
+        Expr::ListGet(_, _, i) | Expr::IsNum(i) => {
+            fold(i);
+            None
+        }
+        Expr::StringGetIndex(s, i) => {
+            fold(s);
+            fold(i);
+            None
+        }
+        _ => None,
+    };
+    if let Some(lit) = folded {
+        *e = Expr::Literal(lit);
+    }
+}
+
+/// Must match what the emitted code would do at runtime.
+fn fold_bin(op: &BinOp, a: &Lit, b: &Lit) -> Option<Lit> {
+    use Lit::*;
+    Some(match (op, a, b) {
+        (BinOp::Add, Num(a), Num(b)) => Num(a + b),
+        (BinOp::Sub, Num(a), Num(b)) => Num(a - b),
+        (BinOp::Mul, Num(a), Num(b)) => Num(a * b),
+        (BinOp::Div, Num(a), Num(b)) => Num(a / b),
+        (BinOp::Mod, Num(a), Num(b)) => Num(a % b),
+        (BinOp::Pow, Num(a), Num(b)) => Num(a.powf(*b)),
+        (BinOp::GT, Num(a), Num(b)) => Bool(a > b),
+        (BinOp::LT, Num(a), Num(b)) => Bool(a < b),
+        (BinOp::EQ, Num(a), Num(b)) => Bool(a == b),
+        (BinOp::EQ, Bool(a), Bool(b)) => Bool(a == b),
+        (BinOp::And, Bool(a), Bool(b)) => Bool(*a && *b),
+        (BinOp::Or, Bool(a), Bool(b)) => Bool(*a || *b),
+        (BinOp::StrJoin, a, b) => Str(format!("{a}{b}")),
+        _ => return None,
+    })
+}
+
+fn fold_un(op: &UnOp, a: &Lit) -> Option<Lit> {
+    match (op, a) {
+        (UnOp::Not, Lit::Bool(b)) => Some(Lit::Bool(!b)),
+        (UnOp::StrLen, a) => Some(Lit::Num(a.to_string().len() as f64)),  // Str::len is bytes
+        _ => None,
+    }
+}
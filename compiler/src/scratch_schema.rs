@@ -4,7 +4,7 @@
 use std::collections::HashMap;
 use serde::{Deserialize, Serialize};
 use serde_json::Result;
-use crate::ast::Expr;
+use crate::ast::{Expr, Lit};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ScratchProject {
@@ -19,6 +19,59 @@ pub struct RawSprite {
     pub lists: HashMap<String, Operand>,
     pub blocks: HashMap<String, Block>,
     pub costumes: Vec<Costume>,
+    /// Drawn bottom to top. Scratch starts scripts from the top.
+    #[serde(default)]
+    pub layerOrder: usize,
+    #[serde(default)]
+    pub comments: HashMap<String, Comment>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Comment {
+    pub text: String,
+}
+
+/// The settings TurboWarp saves in a comment on the stage. Missing fields are what Scratch does.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct TwConfig {
+    pub framerate: u32,
+    pub runtimeOptions: TwRuntimeOptions,
+    pub interpolation: bool,
+    pub turbo: bool,
+    pub hq: bool,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct TwRuntimeOptions {
+    /// Null means no limit since json can't say Infinity.
+    pub maxClones: Option<f64>,
+    pub miscLimits: bool,
+    pub fencing: bool,
+}
+
+impl Default for TwConfig {
+    fn default() -> Self {
+        TwConfig { framerate: 30, runtimeOptions: TwRuntimeOptions::default(), interpolation: false, turbo: false, hq: false, width: 480, height: 360 }
+    }
+}
+
+impl Default for TwRuntimeOptions {
+    fn default() -> Self {
+        TwRuntimeOptions { maxClones: Some(300.0), miscLimits: true, fencing: true }
+    }
+}
+
+impl RawSprite {
+    /// TurboWarp's settings comment is some text, the json, then "// _twconfig_". None if there isn't one.
+    pub fn tw_config(&self) -> Option<Result<TwConfig>> {
+        let text = &self.comments.values().find(|c| c.text.contains("_twconfig_"))?.text;
+        let (start, end) = (text.find('{').unwrap_or(0), text.rfind('}').map_or(0, |i| i + 1));
+        Some(serde_json::from_str(text.get(start..end).unwrap_or("")))
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -197,6 +250,9 @@ pub enum Operand {
     NNSS(usize, (usize, String, String)),
     ListDefault(String, Vec<f64>),
     List(String, Vec<()>),
+    /// A variable with the isCloud flag.
+    CloudNum(String, f64, bool),
+    CloudStr(String, String, bool),
     // Unknown(Value)
 }
 
@@ -204,12 +260,11 @@ pub enum Operand {
 pub struct ArgRef(usize, String, String);
 
 impl Operand {
-    pub fn opt_block(&self) -> Option<&str> {
+    /// None if it's not a reference to a block (an empty substack, a constant, ...).
+    pub fn block_id(&self) -> Option<&str> {
         match self {
-            Operand::ExprRef(_, s) => Some(s.as_str()),
-            Operand::ExprRefExtra(_, s, _) => Some(s.as_str()),
-            Operand::Nothing(_, _) => None,
-            _ => panic!("Expected opt_block found {:?}, maybe could return None but should check", self),
+            Operand::ExprRef(_, s) | Operand::ExprRefExtra(_, s, _) => Some(s.as_str()),
+            _ => None,
         }
     }
 
@@ -220,10 +275,11 @@ impl Operand {
         }
     }
 
-    pub fn unwrap_var(&self) -> &str {
-        match self.opt_var() {
-            Some(s) => s,
-            None => panic!("Failed to unwrap operand var {self:?}"),
+    /// The name from a broadcast menu (primitive type 11).
+    pub fn broadcast_name(&self) -> Option<&str> {
+        match self {
+            Operand::NNSS(_, (11, s, _)) => Some(s.as_ref()),
+            _ => None
         }
     }
 
@@ -235,17 +291,26 @@ impl Operand {
             Operand::List(s, _) |
             Operand::ListDefault(s, _) |
             Operand::VarNum(s, _) |
+            Operand::CloudNum(s, _, _) |
+            Operand::CloudStr(s, _, _) |
             Operand::ArgRef(_, (_, s, _), _) => Some(s),
             Operand::NNSS(_, (_, s, _)) => Some(s),
             _ => None,
         }
     }
 
+    /// Scratch marks them with a flag and a ☁ prefix. Either is enough.
+    pub fn is_cloud(&self) -> bool {
+        matches!(self, Operand::CloudNum(_, _, true) | Operand::CloudStr(_, _, true)) || self.opt_var().is_some_and(|name| name.starts_with("☁ "))
+    }
+
     pub fn var_default_opt(&self) -> Option<Expr> {
         match self {
-            Operand::ArgName(_, val) => Some(Expr::Literal(format!("{val}"))),
-            Operand::VarF(_, val) => Some(Expr::Literal(format!("{val}"))),
-            Operand::VarNum(_, val) => Some(Expr::Literal(format!("{val}"))),
+            Operand::ArgName(_, val) => Some(Expr::constant(val)),
+            Operand::VarF(_, val) => Some(Expr::Literal(Lit::Num(*val))),
+            Operand::VarNum(_, val) => Some(Expr::Literal(Lit::Num(*val as f64))),
+            Operand::CloudNum(_, val, _) => Some(Expr::Literal(Lit::Num(*val))),
+            Operand::CloudStr(_, val, _) => Some(Expr::constant(val)),
             Operand::ListDefault(_, nums) => if nums.is_empty() {
                 Some(Expr::Empty)  // TODO: this case should be different Operand but i the vec eats it. need to reorder
             } else {
@@ -256,31 +321,38 @@ impl Operand {
     }
 }
 
+impl Mutation {
+    /// Run without screen refresh.
+    pub fn is_warp(&self) -> bool {
+        matches!(&self.warp, Some(BoolOrString::B(true))) || matches!(&self.warp, Some(BoolOrString::S(s)) if s == "true")
+    }
+}
+
 impl Input {
-    pub fn unwrap_one(&self) -> &Operand {
+    pub fn opt_one(&self) -> Option<&Operand> {
         match self {
-            Input::NumUn { NUM } => NUM,
-            Input::Val { VALUE } => VALUE,
-            Input::Colour { COLOR } => COLOR,
-            Input::Size { SIZE } => SIZE,
-            Input::Costume { COSTUME } => COSTUME,
-            Input::Custom { custom_block } => custom_block,
+            Input::NumUn { NUM } => Some(NUM),
+            Input::Val { VALUE } => Some(VALUE),
+            Input::Colour { COLOR } => Some(COLOR),
+            Input::Size { SIZE } => Some(SIZE),
+            Input::Costume { COSTUME } => Some(COSTUME),
+            Input::Custom { custom_block } => Some(custom_block),
             Input::Named(vals) => if vals.len() == 1 {
-                    vals.iter().next().unwrap().1
+                    vals.values().next()
                 } else {
-                    panic!("Expected single Operand in Input but found {:?}", self)
+                    None
                 }
-            _ => panic!("Expected single Operand in Input but found {:?}", self)
+            _ => None
         }
     }
 
-    pub fn unwrap_pair(&self) -> (&Operand, &Operand) {
+    pub fn opt_pair(&self) -> Option<(&Operand, &Operand)> {
         match self {
-            Input::NumBin { NUM1, NUM2 } => (NUM1, NUM2),
-            Input::Operands { OPERAND1, OPERAND2 } => (OPERAND1, OPERAND2),
-            Input::Range { FROM, TO } => (FROM, TO),
-            Input::Pos { X, Y } => (X, Y),
-            _ => panic!("Expected two Operand in Input but found {:?}", self)
+            Input::NumBin { NUM1, NUM2 } => Some((NUM1, NUM2)),
+            Input::Operands { OPERAND1, OPERAND2 } => Some((OPERAND1, OPERAND2)),
+            Input::Range { FROM, TO } => Some((FROM, TO)),
+            Input::Pos { X, Y } => Some((X, Y)),
+            _ => None
         }
     }
 }
@@ -288,50 +360,56 @@ impl Input {
 pub enum StopOp {
     ThisScript,
     All,
+    OtherScripts,
 }
 
 impl Field {
     // TODO: damn this sucks, can i offload some to serde?
-    pub fn unwrap_stop(&self) -> StopOp {
+    pub fn opt_stop(&self) -> Option<StopOp> {
         match self {
-            Field::Stop { STOP_OPTION } => {
-                match STOP_OPTION {
-                    Operand::Var(name, _) => {
-                        match name.as_str() {
-                            "this script" => StopOp::ThisScript,
-                            "all" => StopOp::All,
-                            _ => panic!("Expected StopOp found {name}"),
-                        }
-                    }
-                    _ => panic!("Expected Var")
+            Field::Stop { STOP_OPTION: Operand::Var(name, _) } => {
+                match name.as_str() {
+                    "this script" => Some(StopOp::ThisScript),
+                    "all" => Some(StopOp::All),
+                    // The stage's version of the block says stage.
+                    "other scripts in sprite" | "other scripts in stage" => Some(StopOp::OtherScripts),
+                    _ => None,
                 }
             }
-            _ => panic!("Expected Stop found {:?}", self)
+            _ => None
         }
     }
 }
 
 impl Mutation {
+    // These are encoded json strings. Missing or malformed ones are treated as no arguments.
     pub fn arg_ids(&self) -> Vec<String> {
-        serde_json::from_str(self.argumentids.as_ref().unwrap()).unwrap()
+        Self::json_list(&self.argumentids)
     }
 
     pub fn arg_names(&self) -> Vec<String> {
-        serde_json::from_str(self.argumentnames.as_ref().expect("Func Proto")).unwrap()
+        Self::json_list(&self.argumentnames)
     }
 
     pub fn arg_defaults(&self) -> Vec<String> {
-        serde_json::from_str(self.argumentdefaults.as_ref().expect("Func Proto")).unwrap()
+        Self::json_list(&self.argumentdefaults)
+    }
+
+    fn json_list(s: &Option<String>) -> Vec<String> {
+        s.as_deref().and_then(|s| serde_json::from_str(s).ok()).unwrap_or_default()
     }
 
     pub fn arity(&self) -> usize {
-        self.proccode.as_ref().unwrap().chars().filter(|c| *c == '%').count()
+        self.proccode.as_deref().unwrap_or_default().chars().filter(|c| *c == '%').count()
     }
 
-    pub fn name(&self) -> &str {  // TODO: kinda hacky, chops off the %d terms
-        let s = &self.proccode.as_ref().unwrap().as_str()[0..self.proccode.as_ref().unwrap().len()-(self.arity()*3)];
-        assert_eq!(s.chars().filter(|c| *c == '%').count(), 0);
-        s
+    /// Chops off the trailing %s terms. If the arguments are in the middle, the whole proccode is used instead.
+    pub fn name(&self) -> &str {
+        let code = self.proccode.as_deref().unwrap_or_default();
+        match code.len().checked_sub(self.arity() * 3).and_then(|end| code.get(..end)) {
+            Some(s) if !s.contains('%') => s,
+            _ => code,
+        }
     }
 }
 
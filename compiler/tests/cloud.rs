@@ -0,0 +1,34 @@
+mod common;
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use compiler::cli::{Cli, run};
+use compiler::Target;
+
+// The cloud project only ever adds 1 to its cloud variable so on its own it would look like an integer.
+// Another program could have stored any number so a fraction from the cloud file has to survive.
+
+#[test]
+fn cloud_fraction() -> anyhow::Result<()> {
+    env::set_current_dir("..")?;
+    let input = common::build_sb3("cloud")?;
+    let outdir = PathBuf::from("out/gen/cloud");
+    fs::create_dir_all(&outdir)?;
+    // The exe runs in outdir and reads cloud.json from there by default.
+    fs::write(outdir.join("cloud.json"), r#"{"☁ score": "10.5"}"#)?;
+    run(Cli {
+        input,
+        outdir: outdir.clone(),
+        render: Target::Trace,
+        run: true,
+        ..Default::default()
+    })?;
+
+    let trace = fs::read_to_string(outdir.join("trace.txt"))?;
+    assert!(trace.contains("global \"☁ score\" = 11.5"), "{trace}");
+    let stored: HashMap<String, String> = serde_json::from_str(&fs::read_to_string(outdir.join("cloud.json"))?)?;
+    assert_eq!(stored["☁ score"], "11.5");
+    Ok(())
+}
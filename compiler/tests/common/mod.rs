@@ -0,0 +1,26 @@
+//! Helpers shared by the integration tests. The projects are tests/projects/{name}.json with costumes from tests/assets.
+
+use std::fs::{self, File};
+use std::io::Write;
+use zip::write::FileOptions;
+use zip::ZipWriter;
+use compiler::scratch_schema::parse;
+
+/// Zips up the project and the costumes it uses.
+pub fn build_sb3(name: &str) -> anyhow::Result<String> {
+    let raw = fs::read_to_string(format!("tests/projects/{name}.json"))?;
+    fs::create_dir_all("target/test_projects")?;
+    let path = format!("target/test_projects/{name}.sb3");
+    let mut zip = ZipWriter::new(File::create(&path)?);
+    zip.start_file("project.json", FileOptions::default())?;
+    zip.write_all(raw.as_bytes())?;
+    let mut assets: Vec<_> = parse(&raw)?.targets.into_iter().flat_map(|t| t.costumes).map(|c| c.md5ext).collect();
+    assets.sort();
+    assets.dedup();
+    for asset in assets {
+        zip.start_file(&asset, FileOptions::default())?;
+        zip.write_all(&fs::read(format!("tests/assets/{asset}"))?)?;
+    }
+    zip.finish()?;
+    Ok(path)
+}
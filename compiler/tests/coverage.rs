@@ -0,0 +1,41 @@
+mod common;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use compiler::cli::{Cli, run};
+use compiler::coverage::{coverage, Support};
+use compiler::diagnostic::Severity;
+use compiler::scratch_schema::parse;
+
+// Game has one block of each kind (hidelist is partial) and one that isn't under a hat. Clean only uses supported blocks.
+// The report counts them per sprite and shouldn't generate anything.
+
+#[test]
+fn coverage_report() -> anyhow::Result<()> {
+    env::set_current_dir("..")?;
+
+    let report = coverage(parse(&fs::read_to_string("tests/projects/coverage.json")?)?);
+    let [game, clean] = &report.sprites[1..] else { panic!("expected two sprites") };
+    assert_eq!(game.name, "Game");
+    assert_eq!(game.opcodes.get(&(Support::Full, "looks_say".to_string())), Some(&2));
+    assert_eq!(game.opcodes.get(&(Support::Partial, "data_hidelist".to_string())), Some(&1));
+    assert_eq!(game.opcodes.get(&(Support::Unsupported, "extension_doesnotexist".to_string())), Some(&1));
+    assert_eq!(game.unused, 1);
+    assert_eq!((clean.count(Support::Full), clean.count(Support::Partial), clean.count(Support::Unsupported)), (2, 0, 0));
+    assert_eq!(report.worst(), Some(Severity::Warning));
+    let text = report.to_string();
+    assert!(text.contains("Sprite \"Game\": 3 supported, 1 partial, 1 unsupported, 1 unused"), "{text}");
+    assert!(text.contains("Verdict: Compiles but unsupported blocks do nothing."), "{text}");
+
+    let outdir = PathBuf::from("out/gen/coverage");
+    let _ = fs::remove_dir_all(&outdir);
+    run(Cli {
+        input: common::build_sb3("coverage")?,
+        outdir: outdir.clone(),
+        coverage: true,
+        ..Default::default()
+    })?;
+    assert!(!outdir.exists(), "coverage shouldn't generate code");
+    Ok(())
+}
@@ -0,0 +1,40 @@
+mod common;
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use compiler::cli::{Cli, run};
+use compiler::Target;
+
+// The project jumps to a random place every tenth of a second with the pen down.
+// Stopping half way through, the same seed has to draw the same frame every run and another seed a different one.
+
+#[test]
+fn same_seed_same_frame() -> anyhow::Result<()> {
+    env::set_current_dir("..")?;
+    let input = common::build_sb3("deterministic")?;
+    let outdir = PathBuf::from("out/gen/deterministic");
+    run(Cli {
+        input,
+        outdir: outdir.clone(),
+        render: Target::Headless,
+        ..Default::default()
+    })?;
+
+    let first = frame(&outdir, "5")?;
+    let again = frame(&outdir, "5")?;
+    let other = frame(&outdir, "6")?;
+    assert!(first == again, "the same seed drew a different frame");
+    assert!(first != other, "a different seed drew the same frame");
+    Ok(())
+}
+
+fn frame(outdir: &Path, seed: &str) -> anyhow::Result<Vec<u8>> {
+    let status = Command::new("cargo")
+        .args(["run", "--release", "--", "--deterministic", seed, "--frames", "30"])
+        .current_dir(outdir)
+        .status()?;
+    assert!(status.success());
+    Ok(fs::read(outdir.join("frame.png"))?)
+}
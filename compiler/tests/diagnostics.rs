@@ -0,0 +1,52 @@
+mod common;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use compiler::ast::Project;
+use compiler::backend::rust::emit_rust;
+use compiler::cli::{Cli, run};
+use compiler::diagnostic::{CompileError, Severity};
+use compiler::scratch_schema::parse;
+use compiler::{AssetPackaging, Target};
+
+// A project with blocks the parser can't handle has to fail with every problem listed (and where it is) instead of panicking on the first.
+// One that only has blocks it doesn't know or variables that change type still compiles and keeps the warnings.
+// Blocks that parse but the generated code can't do are warnings from emit_rust with the same location.
+
+#[test]
+fn diagnostics() -> anyhow::Result<()> {
+    env::set_current_dir("..")?;
+
+    let input = common::build_sb3("diagnostics")?;
+    let err = run(Cli {
+        input,
+        outdir: PathBuf::from("out/gen/diagnostics"),
+        render: Target::Trace,
+        ..Default::default()
+    }).expect_err("the stop blocks have options that don't exist");
+    let err = err.downcast_ref::<CompileError>().expect("a CompileError");
+    let mut errors: Vec<_> = err.diagnostics.iter().filter(|d| d.is_error()).collect();
+    errors.sort_by_key(|d| d.block_id.clone());
+    assert_eq!(errors.len(), 2, "{err}");
+    for (d, id) in errors.iter().zip(["b1", "b4"]) {
+        assert_eq!(d.sprite.as_deref(), Some("Bad"));
+        assert_eq!(d.opcode.as_deref(), Some("control_stop"));
+        assert_eq!(d.block_id.as_deref(), Some(id));
+    }
+    assert!(err.diagnostics.iter().any(|d| d.severity == Severity::Warning && d.opcode.as_deref() == Some("extension_doesnotexist")), "{err}");
+
+    let raw = fs::read_to_string("tests/projects/type_change.json")?;
+    let project = Project::try_from(parse(&raw)?)?;
+    assert!(project.diagnostics.iter().all(|d| !d.is_error()));
+    let changed = project.diagnostics.iter().find(|d| d.severity == Severity::Warning && d.message.contains("for var v")).expect("a type change warning");
+    assert_eq!((changed.sprite.as_deref(), changed.block_id.as_deref(), changed.opcode.as_deref()), (Some("Main"), Some("b4"), Some("looks_say")));
+
+    let raw = fs::read_to_string("tests/projects/clones.json")?;
+    let project = Project::try_from(parse(&raw)?)?;
+    let (src, warnings) = emit_rust(&project, Target::Trace, AssetPackaging::Embed);
+    assert!(!src.contains("todo!"));
+    let [clone] = warnings.as_slice() else { panic!("{warnings:?}") };
+    assert_eq!((clone.sprite.as_deref(), clone.block_id.as_deref(), clone.opcode.as_deref()), (Some("Cloner"), Some("b2"), Some("control_create_clone_of")));
+    Ok(())
+}
@@ -0,0 +1,104 @@
+#![allow(non_upper_case_globals)]
+mod common;
+
+use std::env;
+use std::fs;
+use std::io::{Cursor, read_to_string};
+use std::path::PathBuf;
+use std::process::Command;
+use zip::ZipArchive;
+use compiler::ast::Project;
+use compiler::backend::interp::{self, Program};
+use compiler::cli::{Cli, run};
+use compiler::scratch_schema::parse;
+use compiler::Target;
+use runtime::backend::trace::{trace, DEFAULT_MAX_TICKS};
+use runtime::RunMode;
+
+// Compiles each project with the trace backend and runs it with the interpreter, then compares both against tests/expected.
+// Set BLESS=1 to overwrite the expected results with what the compiled version did.
+
+const tests: &[&str] = &[
+    "arith",
+    "lists",
+    "procs",
+    "broadcast",
+    "unsupported",
+    "costumes",
+    "type_change",
+    "deterministic",
+    "machine_names",
+    "integers",
+    "bool_text",
+];
+
+/// These yield in loops like Scratch does (trace's --scratch flag) since they're about how scripts interleave.
+const scratch_tests: &[&str] = &[
+    "loop_yield",
+    "stop_others",
+    "restart",
+];
+
+#[test]
+fn differential() -> anyhow::Result<()> {
+    env::set_current_dir("..")?;
+    let bless = env::var("BLESS").is_ok();
+
+    let mut failures = vec![];
+    let all = tests.iter().map(|name| (name, RunMode::Turbo)).chain(scratch_tests.iter().map(|name| (name, RunMode::Scratch)));
+    for (name, mode) in all {
+        let input = common::build_sb3(name)?;
+        let outdir = PathBuf::from(format!("out/gen/{name}"));
+        run(Cli {
+            input: input.clone(),
+            outdir: outdir.clone(),
+            render: Target::Trace,
+            ..Default::default()
+        })?;
+        let mut cmd = Command::new("cargo");
+        cmd.args(["run", "--release"]);
+        if mode == RunMode::Scratch {
+            cmd.args(["--", "--scratch"]);
+        }
+        assert!(cmd.current_dir(&outdir).status()?.success());
+        let compiled = fs::read_to_string(outdir.join("trace.txt"))?;
+        let interpreted = interpret(&input, mode)?;
+
+        let expected_path = format!("tests/expected/{name}.txt");
+        if bless {
+            fs::create_dir_all("tests/expected")?;
+            fs::write(&expected_path, &compiled)?;
+        }
+        match fs::read_to_string(&expected_path) {
+            Ok(expected) => {
+                failures.extend(diff(name, "compiled", &expected, &compiled));
+                failures.extend(diff(name, "interpreted", &expected, &interpreted));
+            }
+            Err(_) => failures.push(format!("{name}: missing {expected_path} (run with BLESS=1 to create it)")),
+        }
+    }
+
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+    Ok(())
+}
+
+fn interpret(input: &str, mode: RunMode) -> anyhow::Result<String> {
+    let mut zip = ZipArchive::new(Cursor::new(fs::read(input)?))?;
+    let raw = read_to_string(zip.by_name("project.json")?)?;
+    let project = Project::try_from(parse(&raw)?)?;
+    interp::load(project, vec![]);
+    Ok(trace::<Program>(DEFAULT_MAX_TICKS, mode))
+}
+
+/// The first line that doesn't match.
+fn diff(name: &str, backend: &str, expected: &str, found: &str) -> Option<String> {
+    let (mut expected, mut found) = (expected.lines(), found.lines());
+    for line in 1.. {
+        match (expected.next(), found.next()) {
+            (None, None) => return None,
+            (a, b) if a == b => {}
+            (a, b) => return Some(format!("{name} ({backend}) line {line}: expected {} but found {}", a.unwrap_or("<end>"), b.unwrap_or("<end>"))),
+        }
+    }
+    unreachable!()
+}
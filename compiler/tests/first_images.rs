@@ -4,6 +4,7 @@ use std::path::PathBuf;
 use std::process::Command;
 use ril::prelude::*;
 use compiler::cli::{Cli, run};
+use compiler::Target;
 
 // TODO: move this to a bin?
 // TODO: add a bin that builds web demos
@@ -47,7 +48,7 @@ fn first_images() -> anyhow::Result<()> {
             run(test.opts())?;
 
             // TODO: put name as text on the image. need to get a font.
-            if let Ok(img) = Image::open(&format!("out/gen/{}/frame.png", test.name)) {
+            if let Ok(img) = Image::open(format!("out/gen/{}/frame.png", test.name)) {
                 image.paste((x * w) as u32, (y * h) as u32, &img.cropped(0, 0, view_w, view_h).resized(w as u32, h as u32, ResizeAlgorithm::Nearest));
             }
         }
@@ -72,6 +73,7 @@ impl T {
             input: format!("target/{}.sb3", name),
             outdir: PathBuf::from(format!("out/gen/{}", name)),
             first_frame_only: true,
+            render: Target::Headless,
             deny_async,
             deny_poly,
             ..Default::default()
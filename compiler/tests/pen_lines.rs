@@ -0,0 +1,77 @@
+#![allow(non_upper_case_globals)]
+mod common;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use ril::prelude::*;
+use compiler::cli::{Cli, run};
+use compiler::Target;
+
+// The headless backend draws pen lines with runtime/src/backend/pen.rs which tries to match scratch exactly.
+// The gpu backends can't be pixel identical but they should be close. This needs a display to run them.
+// The pixels the headless backend draws are covered by runtime/tests/pen.rs.
+
+const tests: &[&str] = &[
+    "pen",
+];
+
+const gpu_backends: &[Target] = &[Target::Macroquad, Target::Notan];
+
+/// Antialiasing at the edge of lines is allowed to differ this much per channel.
+const channel_tolerance: u8 = 64;
+/// Fraction of pixels allowed to be further off than channel_tolerance.
+const max_bad_fraction: f64 = 0.02;
+
+#[test]
+#[ignore = "needs a display for the gpu backends"]
+fn pen_lines() -> anyhow::Result<()> {
+    env::set_current_dir("..")?;
+    fs::create_dir_all("target/pen_lines")?;
+
+    let mut failures = vec![];
+    for name in tests {
+        let input = common::build_sb3(name)?;
+        let expected = first_frame(name, &input, Target::Headless)?;
+        for &backend in gpu_backends {
+            let found = first_frame(name, &input, backend)?;
+            let bad = count_bad_pixels(&expected, &found);
+            let fraction = bad as f64 / (expected.width() * expected.height()) as f64;
+            if fraction > max_bad_fraction {
+                failures.push(format!("{name} ({backend:?}): {bad} pixels ({:.1}%) differ from headless by more than {channel_tolerance}", fraction * 100.0));
+            }
+        }
+    }
+
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+    Ok(())
+}
+
+/// Generated projects have to go in their workspace member folder so each backend overwrites the last.
+fn first_frame(name: &str, input: &str, backend: Target) -> anyhow::Result<Image<Rgb>> {
+    let outdir = PathBuf::from(format!("out/gen/{name}"));
+    run(Cli {
+        input: input.to_string(),
+        outdir: outdir.clone(),
+        render: backend,
+        first_frame_only: true,
+        ..Default::default()
+    })?;
+    let saved = format!("target/pen_lines/{name}_{backend:?}.png");
+    fs::copy(outdir.join("frame.png"), &saved)?;
+    // Windowed backends might have extra padding around the stage.
+    Ok(Image::<Rgb>::open(&saved)?.cropped(0, 0, 480, 360))
+}
+
+fn count_bad_pixels(expected: &Image<Rgb>, found: &Image<Rgb>) -> usize {
+    let mut bad = 0;
+    for y in 0..expected.height() {
+        for x in 0..expected.width() {
+            let (a, b) = (expected.pixel(x, y), found.pixel(x, y));
+            if a.r.abs_diff(b.r) > channel_tolerance || a.g.abs_diff(b.g) > channel_tolerance || a.b.abs_diff(b.b) > channel_tolerance {
+                bad += 1;
+            }
+        }
+    }
+    bad
+}
@@ -0,0 +1,53 @@
+mod common;
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use compiler::cli::{Cli, run};
+use compiler::Target;
+
+// The project asks two questions with a random number between them.
+// Recording a run then replaying it with nothing on stdin has to say exactly the same things.
+
+#[test]
+fn record_then_replay() -> anyhow::Result<()> {
+    env::set_current_dir("..")?;
+    let input = common::build_sb3("replay")?;
+    let outdir = PathBuf::from("out/gen/replay");
+    run(Cli {
+        input,
+        outdir: outdir.clone(),
+        render: Target::Console,
+        ..Default::default()
+    })?;
+
+    let recorded = run_exe(&outdir, &["--deterministic", "3", "--record", "input.txt"], "bob\nred\n")?;
+    let lines: Vec<_> = recorded.lines().collect();
+    assert_eq!(lines.len(), 3, "{recorded}");
+    assert_eq!((lines[0], lines[2]), ("hi bob", "likes red"));
+
+    let recording = fs::read_to_string(outdir.join("input.txt"))?;
+    assert!(recording.starts_with("seed 3\n"), "{recording}");
+    assert!(recording.contains(" answer bob\n") && recording.contains(" answer red\n"), "{recording}");
+
+    let replayed = run_exe(&outdir, &["--replay", "input.txt"], "")?;
+    assert_eq!(replayed, recorded);
+    Ok(())
+}
+
+/// What the program said.
+fn run_exe(outdir: &Path, args: &[&str], stdin: &str) -> anyhow::Result<String> {
+    let mut child = Command::new("cargo")
+        .args(["run", "--release", "--"])
+        .args(args)
+        .current_dir(outdir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    child.stdin.take().unwrap().write_all(stdin.as_bytes())?;
+    let output = child.wait_with_output()?;
+    assert!(output.status.success());
+    Ok(String::from_utf8(output.stdout)?)
+}
@@ -0,0 +1,48 @@
+mod common;
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use compiler::cli::{Cli, run};
+use compiler::Target;
+
+// Saving part way through a run then loading it has to end up on the same frame as running straight through.
+// The project walks a sprite with the pen down, picks a random global and pen colour each step and says them with a counter,
+// so a frame only matches if the globals, sprite variables and position, the running loop, the rng and the pen layer all came back.
+
+#[test]
+fn save_then_load() -> anyhow::Result<()> {
+    env::set_current_dir("..")?;
+    let input = common::build_sb3("save_state")?;
+    let outdir = PathBuf::from("out/gen/save_state");
+    run(Cli {
+        input,
+        outdir: outdir.clone(),
+        render: Target::Headless,
+        save_states: true,
+        ..Default::default()
+    })?;
+
+    run_exe(&outdir, &["--frames", "100"])?;
+    let straight = fs::read(outdir.join("frame.png"))?;
+
+    run_exe(&outdir, &["--frames", "20", "--save", "state.bin"])?;
+    let saved = fs::read(outdir.join("frame.png"))?;
+    assert_ne!(saved, straight, "the save should be from before the end");
+    run_exe(&outdir, &["--frames", "80", "--load", "state.bin"])?;
+    let loaded = fs::read(outdir.join("frame.png"))?;
+
+    assert!(loaded == straight, "loading the save state ended on a different frame");
+    Ok(())
+}
+
+fn run_exe(outdir: &Path, args: &[&str]) -> anyhow::Result<()> {
+    let status = Command::new("cargo")
+        .args(["run", "--release", "--", "--deterministic", "0"])
+        .args(args)
+        .current_dir(outdir)
+        .status()?;
+    assert!(status.success());
+    Ok(())
+}
@@ -0,0 +1,56 @@
+//! Micro-benchmark for the value types generated code uses the most.
+//! Run with `cargo bench -p runtime --bench poly`.
+
+use std::hint::black_box;
+use std::mem::size_of;
+use std::time::Instant;
+use runtime::poly::{List, Poly, Str};
+
+const LEN: usize = 100_000;
+
+fn bench<T>(name: &str, iters: u32, mut f: impl FnMut() -> T) {
+    black_box(f());  // Warm up
+    let start = Instant::now();
+    for _ in 0..iters {
+        black_box(f());
+    }
+    println!("{name:<20} {:?}", start.elapsed() / iters);
+}
+
+/// Half numbers and half computed strings, like a polymorphic list in a sorting project.
+fn mixed_list() -> List<Poly> {
+    let mut list = List::default();
+    for i in 0..LEN {
+        let value = Poly::from(i as f64);
+        list.push(if i % 2 == 0 { value } else { Poly::from(value.as_str()) });
+    }
+    list
+}
+
+fn main() {
+    println!("Poly: {} words, Str: {} words", size_of::<Poly>() / size_of::<usize>(), size_of::<Str>() / size_of::<usize>());
+
+    bench("build list", 20, mixed_list);
+
+    let list = mixed_list();
+    bench("clone list", 50, || list.clone());
+    bench("read as_num", 50, || {
+        (1..=LEN).map(|i| list[i as f64].as_num()).sum::<f64>()
+    });
+    bench("read as_str", 50, || {
+        (1..=LEN).map(|i| list[i as f64].as_str().len()).sum::<f64>()
+    });
+    bench("compare", 50, || {
+        (1..LEN).filter(|&i| list[i as f64] == list[(i + 1) as f64]).count()
+    });
+    bench("swap items", 50, || {
+        // The inner loop of a bubble sort.
+        let mut list = list.clone();
+        for i in 1..LEN {
+            let (a, b) = (list[i as f64].clone(), list[(i + 1) as f64].clone());
+            list.replace(i as f64, b);
+            list.replace((i + 1) as f64, a);
+        }
+        list
+    });
+}
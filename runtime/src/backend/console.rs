@@ -0,0 +1,64 @@
+//! No window. For text programs driven by ask and say (like tres) so they can run in a terminal or be piped in tests.
+//! Say prints plain lines to stdout. Ask reads a line from stdin. Questions go to stderr so stdout is only what the program said.
+//! Drawing is ignored. Exits when every script finishes or stdin closes while a question is waiting.
+
+use std::io::{stdin, stdout, BufRead, Write};
+use std::marker::PhantomData;
+use std::thread;
+use std::time::Duration;
+use crate::{clock, Line, RenderBackend, RenderHandle, ScratchProgram, SEvent, Trigger, World};
+
+pub struct BackendImpl<S>(PhantomData<S>);
+
+pub struct Handle;
+
+impl<S: ScratchProgram<BackendImpl<S>>> RenderBackend<S> for BackendImpl<S> {
+    type Handle<'a> = Handle;
+
+    fn run() {
+        let mut world = World::<S, Self>::new();
+        world.broadcast_toplevel_async(Trigger::FlagClicked);
+        let mut input = stdin().lock();
+        while !world.is_finished() {
+            world.run_frame(&mut Handle);
+
+            // Replays provide their own answers.
+            let waiting = world.current_question.is_some() && world.last_answer.is_none() && world.replay.is_none();
+            if waiting && world.events.is_empty() {
+                let question = world.current_question.as_ref().unwrap();
+                if !question.is_empty() {
+                    eprintln!("{question}");
+                }
+                let mut line = String::new();
+                if input.read_line(&mut line).unwrap() == 0 {
+                    // Nobody will ever answer.
+                    break;
+                }
+                let answer = line.trim_end_matches(['\n', '\r']).to_string();
+                world.events.push_back(SEvent::Answer(answer));
+            } else if !clock::is_deterministic() {
+                // Nothing to draw so don't spin while scripts wait on timers.
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+        world.on_exit(&mut Handle);
+        stdout().flush().unwrap();
+    }
+}
+
+impl RenderHandle for Handle {
+    fn pen_line(&mut self, _: Line) {}
+
+    fn pen_stamp(&mut self, _: (f64, f64), _: usize, _: f64, _: f64) {}
+
+    /// Scratch uses an empty say to hide the bubble. There's nothing to hide here.
+    fn say(&mut self, text: &str, _: (f64, f64)) {
+        if !text.is_empty() {
+            println!("{text}");
+        }
+    }
+
+    fn save_frame(&mut self, _: &str) {}
+
+    fn pen_clear(&mut self) {}
+}
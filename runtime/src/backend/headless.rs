@@ -0,0 +1,61 @@
+//! No window and no gpu. Draws everything with the cpu (see raster.rs) and saves frames as png.
+//! Runs for --frames (default 60) frames, or until the program finishes, then saves frame.png in the current directory.
+//! --first-frame-only is the same as --frames 1. --every-frame also saves frames/frame_{n}.png along the way.
+
+use std::fs;
+use std::marker::PhantomData;
+use std::ops::Add;
+use std::thread;
+use std::time::Duration;
+use crate::backend::raster::Raster;
+use crate::{args, clock, Instant, RenderBackend, RenderHandle, ScratchProgram, Trigger, World};
+
+pub const DEFAULT_FRAMES: usize = 60;
+
+pub struct BackendImpl<S>(PhantomData<S>);
+
+impl<S: ScratchProgram<BackendImpl<S>>> RenderBackend<S> for BackendImpl<S> {
+    type Handle<'a> = Raster;
+
+    fn run() {
+        let frames = if args().any(|arg| arg == "--first-frame-only") {
+            1
+        } else {
+            args().skip_while(|arg| arg != "--frames").nth(1)
+                .map(|n| n.parse().expect("--frames expects a number"))
+                .unwrap_or(DEFAULT_FRAMES)
+        };
+        let every_frame = args().any(|arg| arg == "--every-frame");
+        if every_frame {
+            fs::create_dir_all("frames").unwrap();
+        }
+
+        let mut world = World::<S, Self>::new();
+        let mut handle = Raster::new(&S::get_costumes(), (world.config().width, world.config().height));
+        world.broadcast_toplevel_async(Trigger::FlagClicked);
+
+        for frame in 0..frames {
+            // Timers use the real clock so frames still take real time (unless --deterministic).
+            // Projects above 60 fps want shorter frames.
+            let next_frame = Instant::now().add(Duration::from_millis(16).min(world.config().tick()));
+            world.run_frame(&mut handle);
+            handle.update_sprites(&world.bases);
+            if every_frame {
+                handle.save_frame(&format!("frames/frame_{frame:04}.png"));
+            }
+            if world.is_finished() {
+                break;
+            }
+            if clock::is_deterministic() {
+                continue;
+            }
+            if let Some(wait) = next_frame.checked_duration_since(Instant::now()) {
+                thread::sleep(wait);
+            }
+        }
+
+        world.on_exit(&mut handle);
+        handle.save_frame("frame.png");
+        println!("Exiting. Saved frame at {}/frame.png", std::env::current_dir().unwrap().to_string_lossy());
+    }
+}
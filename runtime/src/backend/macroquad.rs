@@ -1,13 +1,16 @@
 use std::marker::PhantomData;
 use macroquad::prelude::*;
-use crate::{Argb, args, HALF_SCREEN_HEIGHT, HALF_SCREEN_WIDTH, Line, RenderBackend, RenderHandle, ScratchProgram, Trigger, World};
+use crate::backend::pen::MIN_PEN_SIZE;
+use crate::{Argb, args, Line, RenderBackend, RenderHandle, ScratchProgram, Trigger, World};
 use std::ops::{Div, Mul};
 use std::process::exit;
 
 pub struct BackendImpl<S: ScratchProgram<Self>>(PhantomData<S>);
 
 pub struct Handle {
-    costumes: Vec<Texture2D>
+    costumes: Vec<Texture2D>,
+    /// Everything drawn with the pen. Only read directly for save states.
+    pen: RenderTarget,
 }
 
 impl<S: ScratchProgram<BackendImpl<S>>> RenderBackend<S> for BackendImpl<S> {
@@ -16,7 +19,9 @@ impl<S: ScratchProgram<BackendImpl<S>>> RenderBackend<S> for BackendImpl<S> {
     fn run() {
         // TODO: resizable so debugger is less painful
         // TODO: dont include padding if not inspect mode.
-        let (window_width, window_height) = ((HALF_SCREEN_WIDTH * 2.0) as i32 + 400, (HALF_SCREEN_HEIGHT * 2.0) as i32 + 200);
+        // The window is made before the world so read the config the same way it will.
+        let config = S::config().with_args();
+        let (window_width, window_height) = (config.width as i32 + 400, config.height as i32 + 200);
         macroquad::Window::from_config(Conf {
             window_title: "Hctarcs: macroquad".to_string(),
             window_width,
@@ -30,13 +35,14 @@ impl<S: ScratchProgram<BackendImpl<S>>> RenderBackend<S> for BackendImpl<S> {
 
 impl<S: ScratchProgram<BackendImpl<S>>> BackendImpl<S> {
     async fn inner() {
-        let (width, height) = ((HALF_SCREEN_WIDTH * 2.0) as u32, (HALF_SCREEN_HEIGHT * 2.0) as u32);
         let mut world = World::<S, Self>::new();
+        let (width, height) = (world.config().width, world.config().height);
+        let (half_width, half_height) = (world.config().half_width(), world.config().half_height());
         let pen = render_target(width, height);
         pen.texture.set_filter(FilterMode::Nearest);
         let pen_camera = Camera2D {
             render_target: Some(pen.clone()),
-            zoom: vec2(1.0 / HALF_SCREEN_WIDTH as f32, 1.0 / HALF_SCREEN_HEIGHT as f32),
+            zoom: vec2(1.0 / half_width as f32, 1.0 / half_height as f32),
             ..Default::default()
         };
 
@@ -48,7 +54,7 @@ impl<S: ScratchProgram<BackendImpl<S>>> BackendImpl<S> {
             .iter()
             .map(|a| a.get(|bytes| Texture2D::from_file_with_format(bytes, None))).collect();
 
-        let mut handle = Handle { costumes };
+        let mut handle = Handle { costumes, pen: pen.clone() };
         world.broadcast_toplevel_async(Trigger::FlagClicked);
 
         // TODO: move logic out of backend.
@@ -67,21 +73,23 @@ impl<S: ScratchProgram<BackendImpl<S>>> BackendImpl<S> {
 
             clear_background(GRAY);
             // TODO: dynamic scratch window size and scale
-            draw_rectangle(0.0, 0.0, (HALF_SCREEN_WIDTH * 2.0) as f32, (HALF_SCREEN_HEIGHT * 2.0) as f32, WHITE);
+            draw_rectangle(0.0, 0.0, width as f32, height as f32, WHITE);
 
             draw_texture(pen.texture,0.0, 0.0, WHITE);
             for sprite in &world.bases {
                 // TODO: fix wierd coordinate space
                 // println!("{:?}", sprite);
                 if !sprite.hidden {
-                    handle.pen_stamp((sprite.x + HALF_SCREEN_WIDTH, sprite.y - HALF_SCREEN_HEIGHT), sprite.costume, sprite.size_frac);
+                    handle.pen_stamp((sprite.x + half_width, sprite.y - half_height), sprite.costume, sprite.size_frac, sprite.direction);
                 }
             }
 
             if is_key_down(KeyCode::Escape) {
+                world.on_exit(&mut handle);
                 exit(0);
             }
             if take_screenshot {
+                world.on_exit(&mut handle);
                 let img = get_screen_data();
                 img.export_png("frame.png");
                 #[cfg(not(target_arch = "wasm32"))]
@@ -105,18 +113,25 @@ impl<S: ScratchProgram<BackendImpl<S>>> BackendImpl<S> {
 
 // TODO: why is drawing on the different camera in a different coordinate space?
 impl RenderHandle for Handle {
-    fn pen_pixel(&mut self, (x, y): (f64, f64), colour: Argb) {
-        let x = x as f32;
-        let y = -y as f32;
-        // TODO: can i access the frame buffer to draw a single pixel? i guess gpus dont like that.
-        draw_line(x, y, x + 1.0, y + 1.0, 1.0, colour.into());
-    }
-
-    fn pen_line(&mut self, _line: Line) {
-        println!("TODO: pen_line")
+    // Not pixel identical to backend/pen.rs but close enough for compiler/tests/pen_lines.rs
+    fn pen_line(&mut self, line: Line) {
+        let size = line.size.max(MIN_PEN_SIZE) as f32;
+        let (x1, y1) = (line.start.0 as f32, -line.start.1 as f32);
+        let (x2, y2) = (line.end.0 as f32, -line.end.1 as f32);
+        let colour = line.colour.into();
+        if size <= 1.0 {
+            // TODO: can i access the frame buffer to draw a single pixel? i guess gpus dont like that.
+            draw_rectangle(x1, y1, 1.0, 1.0, colour);
+            draw_line(x1, y1, x2, y2, size, colour);
+        } else {
+            draw_line(x1, y1, x2, y2, size, colour);
+            // Scratch lines have round ends.
+            draw_circle(x1, y1, size / 2.0, colour);
+            draw_circle(x2, y2, size / 2.0, colour);
+        }
     }
 
-    fn pen_stamp(&mut self, (x, y): (f64, f64), costume: usize, size: f64) {
+    fn pen_stamp(&mut self, (x, y): (f64, f64), costume: usize, size: f64, direction: f64) {
         let x = x as f32;
         let y = -y as f32;
         // TODO: correct starting costume. shouldn't be in the backend tho
@@ -125,7 +140,7 @@ impl RenderHandle for Handle {
         draw_texture_ex(self.costumes[costume], x - (size.x / 2.0), y - (size.y / 2.0), WHITE, DrawTextureParams {
             dest_size: Some(size),
             source: None,
-            rotation: 0.0,
+            rotation: (direction - 90.0).to_radians() as f32,
             flip_x: false,
             flip_y: false,
             pivot: None,
@@ -150,6 +165,20 @@ impl RenderHandle for Handle {
     fn pen_clear(&mut self) {
         clear_background(WHITE);
     }
+
+    fn pen_pixels(&mut self) -> Option<Vec<u8>> {
+        // Draw calls are batched so finish any still going to the pen texture before reading it.
+        unsafe { get_internal_gl() }.flush();
+        Some(self.pen.texture.get_texture_data().bytes)
+    }
+
+    fn set_pen_pixels(&mut self, rgba: &[u8]) {
+        // The pen_clear before this has to happen first or it would wipe the loaded pixels.
+        unsafe { get_internal_gl() }.flush();
+        let (width, height) = (self.pen.texture.width() as u16, self.pen.texture.height() as u16);
+        assert_eq!(rgba.len(), width as usize * height as usize * 4, "Saved pen layer is the wrong size");
+        self.pen.texture.update(&Image { bytes: rgba.to_vec(), width, height });
+    }
 }
 
 impl From<Argb> for Color {
@@ -1,6 +1,6 @@
 //! By default no backends are enabled.
 //! The generated scratch project must use a cargo feature flag to enable one.
-use crate::{Argb, Line, ScratchProgram};
+use crate::{Line, ScratchProgram};
 
 #[cfg(feature = "render-softbuffer")]
 pub mod softbuffer;
@@ -8,6 +8,15 @@ pub mod softbuffer;
 pub mod notan;
 #[cfg(feature = "render-macroquad")]
 pub mod macroquad;
+#[cfg(feature = "render-trace")]
+pub mod trace;
+#[cfg(feature = "render-headless")]
+pub mod headless;
+#[cfg(feature = "render-console")]
+pub mod console;
+#[cfg(feature = "raster")]
+pub mod raster;
+pub mod pen;
 
 pub trait RenderBackend<S: ScratchProgram<Self>>: Sized {
     type Handle<'a>: RenderHandle;
@@ -18,13 +27,16 @@ pub trait RenderBackend<S: ScratchProgram<Self>>: Sized {
 
 // this might be the same struct as the RenderBackend but it might not want to give a unique reference to everything.
 pub trait RenderHandle {
-    fn pen_pixel(&mut self, pos: (f64, f64), colour: Argb);
     fn pen_line(&mut self, line: Line);
 
-    fn pen_stamp(&mut self, pos: (f64, f64), costume: usize, size: f64);
+    /// Direction is in scratch degrees: clockwise from up, so 90 means not rotated.
+    fn pen_stamp(&mut self, pos: (f64, f64), costume: usize, size: f64, direction: f64);
 
     fn say(&mut self, text: &str, pos: (f64, f64));
 
+    /// Removes every speech bubble. Called when the program is stopped.
+    fn clear_speech(&mut self) {}
+
     fn save_frame(&mut self, _path: &str) {
         todo!("save_frame not implemented on this backend");
     }
@@ -32,4 +44,12 @@ pub trait RenderHandle {
     fn pen_clear(&mut self) {
         todo!("pen_clear")
     }
+
+    /// The pen layer as rgba rows (top to bottom) for save states. None if this backend doesn't draw (trace, console).
+    fn pen_pixels(&mut self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Replaces the pen layer with one from pen_pixels. Called after pen_clear. Backends that don't draw ignore it.
+    fn set_pen_pixels(&mut self, _rgba: &[u8]) {}
 }
@@ -1,36 +1,49 @@
 use notan::draw::*;
 use notan::prelude::*;
+use crate::backend::pen::MIN_PEN_SIZE;
+use crate::backend::raster::{speech_bubble, BUBBLE_GAP};
 use crate::backend::RenderBackend;
-use crate::{Argb, RenderHandle, ScratchProgram, World};
-use crate::builtins::{HALF_SCREEN_HEIGHT, HALF_SCREEN_WIDTH};
+use crate::{args, Argb, Line, RenderHandle, ScratchProgram, SpriteBase, World};
 use crate::sprite::Trigger;
 
 #[derive(AppState)]
 pub struct BackendImpl<S: ScratchProgram<BackendImpl<S>>> {
     state: State,
     world: World<S, BackendImpl<S>>,
+    #[cfg(feature = "inspect")]
+    debugger: crate::ui::Debugger<S, BackendImpl<S>>,
 }
 
 pub struct State {
-    texture: Texture,
+    /// Everything drawn with the pen. Kept between frames.
+    pen: RenderTexture,
     costumes: Vec<Texture>,
-    bytes: Vec<u8>,
-    stamps: Vec<(f32, f32, usize)>
+    /// The most recent say at each position.
+    bubbles: Vec<((f64, f64), Texture)>,
+    /// Copied from the world after each frame so save_frame can draw them.
+    sprites: Vec<SpriteBase>,
+    take_screenshot: bool,
+    /// The stage in pixels (see Config).
+    size: (u32, u32),
 }
 
 pub struct Handle<'frame> {
     state: &'frame mut State,
-    _gfx: &'frame mut Graphics
+    gfx: &'frame mut Graphics,
+    /// Pen operations this frame. Rendered onto the pen texture at the end of the frame.
+    pen: Draw,
 }
 
 impl<S: ScratchProgram<Self>> RenderBackend<S> for BackendImpl<S> {
     type Handle<'a> = Handle<'a>;
 
     fn run() {
-        notan::init_with(BackendImpl::<S>::init)
-            .add_config(DrawConfig)
+        let builder = notan::init_with(BackendImpl::<S>::init)
+            .add_config(DrawConfig);
+        #[cfg(feature = "inspect")]
+        let builder = builder.add_config(notan::egui::EguiConfig);
+        builder
             .draw(BackendImpl::draw)
-            .update(BackendImpl::update)
             .build()
             .unwrap()
     }
@@ -39,100 +52,174 @@ impl<S: ScratchProgram<Self>> RenderBackend<S> for BackendImpl<S> {
 // The notan callbacks arent methods, I just want them to be in the scope of the generic
 impl<S: ScratchProgram<BackendImpl<S>>> BackendImpl<S> {
     fn init(app: &mut App, gfx: &mut Graphics) -> Self {
-        app.window().set_size(2 * HALF_SCREEN_WIDTH as u32, 2 * HALF_SCREEN_HEIGHT as u32);
+        let world = World::new();
+        let (width, height) = (world.config().width, world.config().height);
+        // Leave room for the debugger windows like macroquad does.
+        #[cfg(feature = "inspect")]
+        app.window().set_size(width + 400, height + 200);
+        #[cfg(not(feature = "inspect"))]
+        app.window().set_size(width, height);
         app.window().set_title("Hctarcs: notan");
-        let (width, height) = app.window().size();
-        let len = (width * height * 4) as usize;
-        let bytes = vec![0; len];
-
-        let texture = gfx
-            .create_texture()
-            .from_bytes(&bytes, width, height)
-            .build()
-            .unwrap();
 
+        let pen = gfx.create_render_texture(width, height).build().unwrap();
         let costumes = S::get_costumes().iter().map(|a| a.get(|bytes| gfx.create_texture().from_image(bytes).build().unwrap())).collect();
 
         let mut s = Self {
-            state: State { texture, costumes, bytes, stamps: vec![] },
-            world: World::new(),
+            state: State { pen, costumes, bubbles: vec![], sprites: vec![], take_screenshot: args().any(|arg| &arg == "--first-frame-only"), size: (width, height) },
+            world,
+            #[cfg(feature = "inspect")]
+            debugger: crate::ui::Debugger::new(),
         };
         s.world.broadcast_toplevel_async(Trigger::FlagClicked);
         s
     }
 
-    fn draw(gfx: &mut Graphics, state: &mut Self) {
+    fn draw(app: &mut App, gfx: &mut Graphics, _plugins: &mut Plugins, state: &mut Self) {
+        // Checked here instead of in update so on_exit gets a render handle.
+        let escape = app.keyboard.was_pressed(KeyCode::Escape);
+        let closing = escape || state.state.take_screenshot;
+        let pen = state.state.pen.create_draw();
         let mut handle = Handle {
             state: &mut state.state,
-            _gfx: gfx,
+            gfx: &mut *gfx,
+            pen,
         };
+        state.world.run_frame(&mut handle);
+        handle.flush_pen();
+        if closing {
+            state.world.on_exit(&mut handle);
+        }
+        state.state.sprites.clear();
+        state.state.sprites.extend(state.world.bases.iter().cloned());
 
-        // TODO: use run_frame once inspector supported here
-        state.world.poll_turbo(&mut handle);
-
-        // Update the texture with the new data
-        // TODO: have a dirty flag so dont do this on frames that didn't use the pen
-        gfx.update_texture(&mut state.state.texture)
-            .with_data(&state.state.bytes)
-            .update()
-            .unwrap();
+        if state.state.take_screenshot {
+            save_stage(gfx, &state.state, "frame.png");
+            println!("Exiting. Saved first frame at {}/frame.png", std::env::current_dir().unwrap().to_string_lossy());
+            app.exit();
+            return;
+        }
+        if escape {
+            app.exit();
+            return;
+        }
 
         let mut draw = gfx.create_draw();
-        draw.clear(Color::WHITE);
-        for (x, y, costume) in &state.state.stamps {
-            let img = &state.state.costumes[*costume];
-            let scale = 0.5; // 100.0 / img.width();
-            // TODO: this is wrong. macroquad does tests/stamp_pos correctly
-            let (x, y) = ((*x * 2.0) + (img.size().0 * 2.0) + HALF_SCREEN_WIDTH as f32, (*y * 2.0) + (img.size().1) + HALF_SCREEN_HEIGHT as f32);
-            draw
-                .image(img)
-                .position(x, y)
-                // .position(*x, *y)
-                .scale(scale, scale)
-            ;
-        }
-        // TODO: the stamps need to be drawn onto this texture gradually instead.
-        // There's an impl CreateDraw for RenderTexture
-        // can i do like this but no shaders https://github.com/Nazariglez/notan/blob/main/examples/renderer_render_texture.rs
-        draw.image(&state.state.texture);
-        // TODO: draw current sprites
+        draw.clear(Color::GRAY);
+        draw_stage(&mut draw, &state.state);
         gfx.render(&draw);
+
+        #[cfg(feature = "inspect")]
+        {
+            use notan::egui::EguiPluginSugar;
+            let output = _plugins.egui(|ctx| state.debugger.frame(ctx, &mut state.world));
+            gfx.render(&output);
+        }
     }
+}
 
+/// The pen layer on white with visible sprites and speech on top. Same order as raster.rs
+fn draw_stage(draw: &mut Draw, state: &State) {
+    draw.rect((0.0, 0.0), (state.size.0 as f32, state.size.1 as f32)).color(Color::WHITE);
+    draw.image(&state.pen);
+    for sprite in state.sprites.iter().filter(|s| !s.hidden) {
+        stamp(draw, state.size, &state.costumes[sprite.costume], (sprite.x, sprite.y), sprite.size_frac, sprite.direction);
+    }
+    for (pos, img) in &state.bubbles {
+        let (x, y) = to_screen(state.size, *pos);
+        draw.image(img).position(x, y - BUBBLE_GAP as f32 - img.height());
+    }
+}
 
-    fn update(app: &mut App, _state: &mut Self) {
-        if app.keyboard.was_pressed(KeyCode::Escape) {
-            app.exit();
-        }
+/// Draws the stage to an offscreen texture and reads it back.
+fn save_stage(gfx: &mut Graphics, state: &State, path: &str) {
+    let (width, height) = state.size;
+    let target = gfx.create_render_texture(width, height).build().unwrap();
+    let mut draw = target.create_draw();
+    draw_stage(&mut draw, state);
+    gfx.render_to(&target, &draw);
+    let mut bytes = vec![0; (width * height * 4) as usize];
+    gfx.read_pixels(&target).read_to(&mut bytes).unwrap();
+    image::save_buffer(path, &bytes, width, height, image::ColorType::Rgba8).unwrap();
+}
+
+/// Scratch coordinates have the origin in the middle of the stage and y pointing up.
+fn to_screen((width, height): (u32, u32), (x, y): (f64, f64)) -> (f32, f32) {
+    ((x + width as f64 / 2.0) as f32, (height as f64 / 2.0 - y) as f32)
+}
+
+/// Centered on pos. Size is the same as macroquad uses (costumes are double resolution).
+fn stamp(draw: &mut Draw, stage: (u32, u32), img: &Texture, pos: (f64, f64), size: f64, direction: f64) {
+    let (x, y) = to_screen(stage, pos);
+    let (w, h) = (img.width() * size as f32 / 2.0, img.height() * size as f32 / 2.0);
+    draw.image(img)
+        .position(x - (w / 2.0), y - (h / 2.0))
+        .size(w, h)
+        // Scratch directions are clockwise degrees from up and 90 is the costume's natural orientation.
+        .rotate_degrees_from((x, y), (direction - 90.0) as f32);
+}
+
+impl<'a> Handle<'a> {
+    fn flush_pen(&mut self) {
+        self.gfx.render_to(&self.state.pen, &self.pen);
+        self.pen = self.state.pen.create_draw();
     }
 }
 
 impl<'a> RenderHandle for Handle<'a> {
-    fn pen_pixel(&mut self, (x, y): (f64, f64), colour: Argb) {
-        // TODO: underflow check
-        let x = (x + HALF_SCREEN_WIDTH) as usize;
-        let y = (HALF_SCREEN_HEIGHT - y) as usize;
-        let i = (x + (y * (HALF_SCREEN_WIDTH as u32 * 2) as usize)) * 4;
-        if i < self.state.bytes.len() {
-            self.state.bytes[i..i + 4].copy_from_slice(&Color::from(colour).rgba_u8());
+    // Not pixel identical to backend/pen.rs but close enough for compiler/tests/pen_lines.rs
+    fn pen_line(&mut self, line: Line) {
+        let (start, end) = (to_screen(self.state.size, line.start), to_screen(self.state.size, line.end));
+        let colour: Color = line.colour.into();
+        let size = line.size.max(MIN_PEN_SIZE) as f32;
+        self.pen.line(start, end).width(size).color(colour);
+        // Scratch lines have round ends.
+        self.pen.circle(size / 2.0).position(start.0, start.1).color(colour);
+        self.pen.circle(size / 2.0).position(end.0, end.1).color(colour);
+    }
+
+    fn pen_stamp(&mut self, pos: (f64, f64), costume: usize, size: f64, direction: f64) {
+        stamp(&mut self.pen, self.state.size, &self.state.costumes[costume], pos, size, direction);
+    }
+
+    fn say(&mut self, text: &str, pos: (f64, f64)) {
+        self.state.bubbles.retain(|(p, _)| *p != pos);
+        if !text.is_empty() {
+            let img = speech_bubble(text);
+            let texture = self.gfx.create_texture().from_bytes(&img, img.width(), img.height()).build().unwrap();
+            self.state.bubbles.push((pos, texture));
         }
     }
 
-    fn pen_line(&mut self, line: crate::Line) {
-        println!("TODO: pen_line {line:?}")
+    fn clear_speech(&mut self) {
+        self.state.bubbles.clear();
+    }
+
+    fn save_frame(&mut self, path: &str) {
+        self.flush_pen();
+        save_stage(self.gfx, self.state, path);
+    }
+
+    fn pen_clear(&mut self) {
+        // Throws away anything drawn earlier this frame too.
+        self.pen = self.state.pen.create_draw();
+        self.pen.clear(Color::TRANSPARENT);
     }
 
-    fn pen_stamp(&mut self, (x, y): (f64, f64), costume: usize, size: f64) {
-        // TODO: use size
-        let x = (x) as f32;
-        let y = (-y) as f32;
-        assert!(costume < self.state.costumes.len());
-        self.state.stamps.push((x, y, costume));
-        println!("stamp {x}, {y} {costume}")
+    fn pen_pixels(&mut self) -> Option<Vec<u8>> {
+        self.flush_pen();
+        let (width, height) = self.state.size;
+        let mut bytes = vec![0; (width * height * 4) as usize];
+        self.gfx.read_pixels(&self.state.pen).read_to(&mut bytes).unwrap();
+        Some(bytes)
     }
 
-    fn say(&mut self, text: &str, _: (f64, f64)) {
-        println!("TODO: [SAY] {text}")
+    fn set_pen_pixels(&mut self, rgba: &[u8]) {
+        let (width, height) = self.state.size;
+        assert_eq!(rgba.len(), (width * height * 4) as usize, "Saved pen layer is the wrong size");
+        let texture = self.gfx.create_texture().from_bytes(rgba, width, height).build().unwrap();
+        // Drawn over the cleared pen layer so there's nothing underneath to blend with.
+        self.pen.image(&texture);
+        self.flush_pen();
     }
 }
 
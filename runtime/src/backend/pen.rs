@@ -0,0 +1,45 @@
+//! The one pen line rasterizer. Cpu backends draw with this so they produce identical pixels
+//! and the gpu backends are checked against it (compiler/tests/pen_lines.rs).
+
+use crate::Line;
+
+/// Scratch clamps the pen size to this range. FrameCtx does unless misc limits are off (see Config).
+/// Renderers still don't go below the minimum.
+pub const MIN_PEN_SIZE: f64 = 1.0;
+pub const MAX_PEN_SIZE: f64 = 1200.0;
+
+/// Calls plot(x, y, coverage) for every pixel the line touches, where coverage is between 0 and 1.
+/// Pixels are in screen space (origin top left, y down) and clipped to width*height.
+///
+/// This copies what scratch-render does:
+/// - The line is a capsule with diameter of pen size. So it has round ends and a zero length line is a dot.
+/// - The edge is antialiased over one pixel.
+/// - 1px and 3px lines are nudged half a pixel so they land on pixel centers instead of blurring across two.
+pub fn rasterize_line(line: &Line, (width, height): (u32, u32), mut plot: impl FnMut(u32, u32, f64)) {
+    let size = line.size.max(MIN_PEN_SIZE);
+    let offset = if size == 1.0 || size == 3.0 { 0.5 } else { 0.0 };
+    let to_screen = |(x, y): (f64, f64)| (x + offset + width as f64 / 2.0, height as f64 / 2.0 - (y + offset));
+    let (start, end) = (to_screen(line.start), to_screen(line.end));
+
+    let radius = size / 2.0;
+    let reach = radius + 0.5;
+    let min_x = (start.0.min(end.0) - reach).floor().max(0.0) as u32;
+    let min_y = (start.1.min(end.1) - reach).floor().max(0.0) as u32;
+    let max_x = ((start.0.max(end.0) + reach).ceil().max(0.0) as u32).min(width);
+    let max_y = ((start.1.max(end.1) + reach).ceil().max(0.0) as u32).min(height);
+
+    let (dx, dy) = (end.0 - start.0, end.1 - start.1);
+    let len_sq = dx * dx + dy * dy;
+    for y in min_y..max_y {
+        for x in min_x..max_x {
+            let (px, py) = (x as f64 + 0.5, y as f64 + 0.5);
+            // Closest point on the segment.
+            let t = if len_sq == 0.0 { 0.0 } else { (((px - start.0) * dx + (py - start.1) * dy) / len_sq).clamp(0.0, 1.0) };
+            let (cx, cy) = (start.0 + t * dx - px, start.1 + t * dy - py);
+            let coverage = (reach - (cx * cx + cy * cy).sqrt()).clamp(0.0, 1.0);
+            if coverage > 0.0 {
+                plot(x, y, coverage);
+            }
+        }
+    }
+}
@@ -0,0 +1,219 @@
+//! Cpu drawing shared by the backends that don't have a graphics library to do it for them (headless and softbuffer).
+//! Everything is drawn into an RgbaImage then the backend decides what to do with it.
+
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use embedded_graphics::mono_font::ascii::FONT_6X10;
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::pixelcolor::{Rgb888, RgbColor};
+use embedded_graphics::prelude::{DrawTarget, OriginDimensions, Point, Size};
+use embedded_graphics::text::{Baseline, Text};
+use embedded_graphics::{Drawable, Pixel};
+use image::{Rgba, RgbaImage};
+use crate::backend::pen::rasterize_line;
+use crate::{Argb, Line, RenderHandle, ScratchAsset, SpriteBase};
+
+pub struct Raster {
+    pen: RgbaImage,
+    costumes: Vec<RgbaImage>,
+    /// The most recent say at each position. Drawn on top of everything but not onto the pen layer.
+    bubbles: Vec<(String, (f64, f64))>,
+    /// Copied from the world after each frame so save_frame can draw them.
+    sprites: Vec<SpriteBase>,
+}
+
+// Costumes that fail to decode are drawn as nothing rather than crashing the whole program.
+fn decode(bytes: &[u8]) -> RgbaImage {
+    match image::load_from_memory(bytes) {
+        Ok(img) => img.into_rgba8(),
+        Err(e) => {
+            println!("Failed to decode costume: {e}");
+            RgbaImage::new(1, 1)
+        }
+    }
+}
+
+/// Scratch coordinates have the origin in the middle of the stage and y pointing up.
+fn to_screen(target: &RgbaImage, (x, y): (f64, f64)) -> (f64, f64) {
+    (x + target.width() as f64 / 2.0, target.height() as f64 / 2.0 - y)
+}
+
+impl Raster {
+    /// Size is the stage's in pixels (see Config).
+    pub fn new(costumes: &[ScratchAsset], (width, height): (u32, u32)) -> Self {
+        Raster {
+            pen: RgbaImage::new(width, height),
+            costumes: costumes.iter().map(|a| a.get(decode)).collect(),
+            bubbles: vec![],
+            sprites: vec![],
+        }
+    }
+
+    /// Call at the end of each frame.
+    pub fn update_sprites(&mut self, bases: &VecDeque<SpriteBase>) {
+        self.sprites.clear();
+        self.sprites.extend(bases.iter().cloned());
+    }
+
+    /// The pen layer on white with visible sprites and speech on top.
+    pub fn composite(&self) -> RgbaImage {
+        let mut frame = RgbaImage::from_pixel(self.pen.width(), self.pen.height(), Rgba([255, 255, 255, 255]));
+        for (dest, src) in frame.pixels_mut().zip(self.pen.pixels()) {
+            blend(dest, *src);
+        }
+        for sprite in self.sprites.iter().filter(|s| !s.hidden) {
+            stamp(&mut frame, &self.costumes[sprite.costume], (sprite.x, sprite.y), sprite.size_frac, sprite.direction);
+        }
+        for (text, pos) in &self.bubbles {
+            bubble(&mut frame, text, *pos);
+        }
+        frame
+    }
+}
+
+impl RenderHandle for Raster {
+    fn pen_line(&mut self, line: Line) {
+        let colour: Rgba<u8> = line.colour.into();
+        let pen = &mut self.pen;
+        rasterize_line(&line, pen.dimensions(), |x, y, coverage| {
+            let mut c = colour;
+            c.0[3] = (c.0[3] as f64 * coverage).round() as u8;
+            blend(pen.get_pixel_mut(x, y), c);
+        });
+    }
+
+    fn pen_stamp(&mut self, pos: (f64, f64), costume: usize, size: f64, direction: f64) {
+        stamp(&mut self.pen, &self.costumes[costume], pos, size, direction);
+    }
+
+    fn say(&mut self, text: &str, pos: (f64, f64)) {
+        self.bubbles.retain(|(_, p)| *p != pos);
+        if !text.is_empty() {
+            self.bubbles.push((text.to_string(), pos));
+        }
+    }
+
+    fn clear_speech(&mut self) {
+        self.bubbles.clear();
+    }
+
+    fn save_frame(&mut self, path: &str) {
+        self.composite().save(path).unwrap();
+    }
+
+    fn pen_clear(&mut self) {
+        self.pen = RgbaImage::new(self.pen.width(), self.pen.height());
+    }
+
+    fn pen_pixels(&mut self) -> Option<Vec<u8>> {
+        Some(self.pen.as_raw().clone())
+    }
+
+    fn set_pen_pixels(&mut self, rgba: &[u8]) {
+        self.pen = RgbaImage::from_raw(self.pen.width(), self.pen.height(), rgba.to_vec()).expect("Saved pen layer is the wrong size");
+    }
+}
+
+/// Draws the costume centered on pos. Size is the same as macroquad uses (costumes are double resolution).
+/// Walks the destination pixels and maps each back into the costume so rotation doesn't leave holes.
+fn stamp(target: &mut RgbaImage, img: &RgbaImage, pos: (f64, f64), size: f64, direction: f64) {
+    let scale = size / 2.0;
+    if scale <= 0.0 {
+        return;
+    }
+    let (cx, cy) = to_screen(target, pos);
+    // Scratch directions are clockwise degrees from up and 90 is the costume's natural orientation.
+    let (sin, cos) = (direction - 90.0).to_radians().sin_cos();
+    let (w, h) = (img.width() as f64, img.height() as f64);
+    let reach = (w * w + h * h).sqrt() * scale / 2.0;
+    let min_x = (cx - reach).floor().max(0.0) as u32;
+    let min_y = (cy - reach).floor().max(0.0) as u32;
+    let max_x = ((cx + reach).ceil().max(0.0) as u32).min(target.width());
+    let max_y = ((cy + reach).ceil().max(0.0) as u32).min(target.height());
+    for y in min_y..max_y {
+        for x in min_x..max_x {
+            let (dx, dy) = (x as f64 + 0.5 - cx, y as f64 + 0.5 - cy);
+            let u = (dx * cos + dy * sin) / scale + w / 2.0;
+            let v = (dy * cos - dx * sin) / scale + h / 2.0;
+            if u >= 0.0 && v >= 0.0 && u < w && v < h {
+                blend(target.get_pixel_mut(x, y), *img.get_pixel(u as u32, v as u32));
+            }
+        }
+    }
+}
+
+/// How far above the speaker the bottom of a speech bubble goes.
+pub const BUBBLE_GAP: f64 = 10.0;
+
+fn bubble(target: &mut RgbaImage, text: &str, pos: (f64, f64)) {
+    let img = speech_bubble(text);
+    let (x, y) = to_screen(target, pos);
+    let (left, top) = (x as i64, (y - BUBBLE_GAP) as i64 - img.height() as i64);
+    image::imageops::overlay(target, &img, left, top);
+}
+
+// TODO: real speech bubble shape and line wrapping
+/// Black text in a box. Backends with a gpu still use this so they don't need to ship a font.
+pub fn speech_bubble(text: &str) -> RgbaImage {
+    let style = MonoTextStyle::new(&FONT_6X10, Rgb888::new(0, 0, 0));
+    let (w, h) = (text.chars().count() as u32 * 6 + 8, 10 + 8);
+    let mut img = RgbaImage::from_fn(w, h, |x, y| {
+        if x == 0 || y == 0 || x == w - 1 || y == h - 1 { Rgba([160, 160, 160, 255]) } else { Rgba([255, 255, 255, 255]) }
+    });
+    let _ = Text::with_baseline(text, Point::new(4, 4), style, Baseline::Top).draw(&mut Canvas(&mut img));
+    img
+}
+
+/// Alpha blend src over dest.
+fn blend(dest: &mut Rgba<u8>, src: Rgba<u8>) {
+    let a = src.0[3] as u32;
+    if a == 255 {
+        *dest = src;
+        return;
+    }
+    let out_a = a + dest.0[3] as u32 * (255 - a) / 255;
+    if out_a == 0 {
+        return;
+    }
+    for i in 0..3 {
+        let d = dest.0[i] as u32 * dest.0[3] as u32 * (255 - a) / 255;
+        dest.0[i] = ((src.0[i] as u32 * a + d) / out_a) as u8;
+    }
+    dest.0[3] = out_a as u8;
+}
+
+// The high byte is transparency not opacity.
+impl From<Argb> for Rgba<u8> {
+    fn from(value: Argb) -> Self {
+        Rgba([
+            ((value.0 >> 16) & 255) as u8,
+            ((value.0 >> 8) & 255) as u8,
+            (value.0 & 255) as u8,
+            255 - ((value.0 >> 24) & 255) as u8,
+        ])
+    }
+}
+
+struct Canvas<'a>(&'a mut RgbaImage);
+
+impl OriginDimensions for Canvas<'_> {
+    fn size(&self) -> Size {
+        Size::new(self.0.width(), self.0.height())
+    }
+}
+
+impl DrawTarget for Canvas<'_> {
+    type Color = Rgb888;
+    type Error = Infallible;
+
+    fn draw_iter<I: IntoIterator<Item=Pixel<Rgb888>>>(&mut self, pixels: I) -> Result<(), Infallible> {
+        for Pixel(p, c) in pixels {
+            if p.x >= 0 && p.y >= 0 {
+                if let Some(dest) = self.0.get_pixel_mut_checked(p.x as u32, p.y as u32) {
+                    *dest = Rgba([c.r(), c.g(), c.b(), 255]);
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,59 +1,67 @@
 use std::marker::PhantomData;
 use std::num::NonZeroU32;
-use softbuffer::{Buffer, Surface};
+use std::ops::Add;
+use std::time::Duration;
+use softbuffer::Surface;
 use winit::dpi::{PhysicalSize, Size};
-use winit::event::{Event, KeyEvent, WindowEvent};
+use winit::event::{Event, KeyEvent, StartCause, WindowEvent};
 use winit::event_loop::{ControlFlow, EventLoop};
 use winit::keyboard::{Key, NamedKey};
 use winit::window::{Window, WindowBuilder};
+use crate::backend::raster::Raster;
 use crate::backend::RenderBackend;
-use crate::builtins::{HALF_SCREEN_HEIGHT, HALF_SCREEN_WIDTH};
 use crate::sprite::Trigger;
-use crate::{Argb, Line, RenderHandle, ScratchProgram, World};
+use crate::{args, Instant, RenderHandle, ScratchProgram, World};
 
 // Some backends want the generic so they can store the world so its easier to just put it on all backends.
 pub struct BackendImpl<S>(PhantomData<S>);
 
-pub struct Handle<'a> {
-    buffer: Buffer<'a, &'static Window, &'static Window>,
-}
-
+/// All the drawing happens on the cpu in raster.rs, softbuffer just gets the finished frame onto the window.
 impl<S: ScratchProgram<BackendImpl<S>>> RenderBackend<S> for BackendImpl<S> {
-    type Handle<'a> = Handle<'a>;
+    type Handle<'a> = Raster;
 
     fn run() {
+        let mut world = World::<S, Self>::new();
+        let (width, height) = (world.config().width, world.config().height);
         let event_loop = EventLoop::new().unwrap();
         let builder = WindowBuilder::new().with_title("Hctarcs: softbuffer");
         // If you think this is dumb I dare you to fix it.
-        let window = &*Box::leak(Box::new(builder.build(&event_loop).unwrap()));
+        let window: &'static Window = Box::leak(Box::new(builder.build(&event_loop).unwrap()));
         window.set_resizable(false);
-        let _ = window.request_inner_size(Size::Physical(PhysicalSize::new(480, 360)));
+        let _ = window.request_inner_size(Size::Physical(PhysicalSize::new(width, height)));
         let context = softbuffer::Context::new(window).unwrap();
-
-        let mut world = World::<S, Self>::new();
         let mut surface = Surface::new(&context, window).unwrap();
-        surface
-            .resize(
-                NonZeroU32::new((HALF_SCREEN_WIDTH * 2.0) as u32).unwrap(),
-                NonZeroU32::new((HALF_SCREEN_HEIGHT * 2.0) as u32).unwrap(),
-            )
-            .unwrap();
+        surface.resize(NonZeroU32::new(width).unwrap(), NonZeroU32::new(height).unwrap()).unwrap();
 
+        let mut handle = Raster::new(&S::get_costumes(), (width, height));
         world.broadcast_toplevel_async(Trigger::FlagClicked);
-        let mut handle = Handle { buffer: surface.buffer_mut().unwrap() };
-        world.poll_until_waiting(&mut handle);
-        handle.buffer.present().unwrap();
+        let take_screenshot = args().any(|arg| &arg == "--first-frame-only");
 
-        event_loop.set_control_flow(ControlFlow::Wait);  // TODO: Poll?
-
-        event_loop.run(|event, elwt| {
+        event_loop.run(move |event, elwt| {
             match event {
-                Event::WindowEvent { event: WindowEvent::RedrawRequested, .. } => {
-                    // TODO: should render here but it resets the buffer every time.
-                    //       need to implement my own saving the pen texture buffer but idc about this backend that much rn
+                // There's no vsync so wake up every 16ms (or faster for projects above 60 fps) to run a frame.
+                Event::NewEvents(StartCause::Init | StartCause::ResumeTimeReached { .. }) => {
+                    elwt.set_control_flow(ControlFlow::WaitUntil(Instant::now().add(Duration::from_millis(16).min(world.config().tick()))));
+                    world.run_frame(&mut handle);
+                    handle.update_sprites(&world.bases);
+                    if take_screenshot {
+                        world.on_exit(&mut handle);
+                        handle.save_frame("frame.png");
+                        println!("Exiting. Saved first frame at {}/frame.png", std::env::current_dir().unwrap().to_string_lossy());
+                        elwt.exit();
+                        return;
+                    }
+                    window.request_redraw();
                 }
-                Event::AboutToWait => {
-                    // ? window.request_redraw();
+                Event::WindowEvent { event: WindowEvent::RedrawRequested, .. } => {
+                    let frame = handle.composite();
+                    let mut buffer = surface.buffer_mut().unwrap();
+                    // softbuffer wants 0RGB
+                    for (dest, src) in buffer.iter_mut().zip(frame.pixels()) {
+                        let [r, g, b, _] = src.0;
+                        *dest = ((r as u32) << 16) | ((g as u32) << 8) | b as u32;
+                    }
+                    buffer.present().unwrap();
                 }
 
                 Event::WindowEvent { event: WindowEvent::CloseRequested |
@@ -63,32 +71,12 @@ impl<S: ScratchProgram<BackendImpl<S>>> RenderBackend<S> for BackendImpl<S> {
                             ..
                         }, ..
                     }, ..
-                } => elwt.exit(),
+                } => {
+                    world.on_exit(&mut handle);
+                    elwt.exit();
+                }
                 _ => {}
             }
         }).unwrap();
     }
 }
-
-impl<'a> RenderHandle for Handle<'a> {
-    fn pen_pixel(&mut self, (x, y): (f64, f64), colour: Argb) {
-        let x = (x + HALF_SCREEN_WIDTH) as usize;
-        let y = (HALF_SCREEN_HEIGHT - y) as usize;
-        let i = x + (y * (HALF_SCREEN_WIDTH * 2.0) as usize);
-        if i > 0 && i < self.buffer.len() {
-            self.buffer[i] = colour.0;
-        }
-    }
-
-    fn pen_line(&mut self, line: Line) {
-        println!("TODO: pen_line {line:?}")
-    }
-
-    fn pen_stamp(&mut self, pos: (f64, f64), costume: usize, size: f64) {
-        println!("TODO: pen_stamp {pos:?} {costume:?} {size:?}")
-    }
-
-    fn say(&mut self, text: &str, _: (f64, f64)) {
-        println!("TODO: [SAY] {text}")
-    }
-}
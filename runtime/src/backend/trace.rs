@@ -0,0 +1,110 @@
+//! Runs without a window and records what the program did so tests can compare it against an expected result.
+//! The compiled program writes its trace to trace.txt in the current directory.
+//! Pass --scratch to yield in loops like RunMode::Scratch so the trace shows how scripts interleave.
+
+use std::fmt::{Arguments, Write};
+use std::fs;
+use std::marker::PhantomData;
+use crate::{args, clock, Line, RenderBackend, RenderHandle, RunMode, ScratchProgram, Sprite, Trigger, World};
+
+/// Give up on programs that haven't finished after this many polls. Override with --ticks.
+pub const DEFAULT_MAX_TICKS: usize = 100_000;
+
+pub struct BackendImpl<S>(PhantomData<S>);
+
+/// Pen drawing is summarised as a count and hash since programs like mandelbrot draw every pixel.
+pub struct Handle {
+    out: String,
+    pen_ops: usize,
+    pen_hash: u64,
+}
+
+impl<S: ScratchProgram<BackendImpl<S>>> RenderBackend<S> for BackendImpl<S> {
+    type Handle<'a> = Handle;
+
+    fn run() {
+        let max_ticks = args().skip_while(|arg| arg != "--ticks").nth(1)
+            .map(|n| n.parse().expect("--ticks expects a number"))
+            .unwrap_or(DEFAULT_MAX_TICKS);
+        let mode = if args().any(|arg| arg == "--scratch") { RunMode::Scratch } else { RunMode::Turbo };
+        fs::write("trace.txt", trace::<S>(max_ticks, mode)).unwrap();
+    }
+}
+
+/// Runs the program until every script finishes (or max_ticks polls) then lists the final value of every variable.
+/// Always deterministic with seed 0. Usually RunMode::Turbo since only the final values matter.
+pub fn trace<S: ScratchProgram<BackendImpl<S>>>(max_ticks: usize, mode: RunMode) -> String {
+    let mut world = World::<S, BackendImpl<S>>::new();
+    // Traces are compared against each other so they can't depend on timing or random numbers.
+    world.set_deterministic(0);
+    world.mode = mode;
+    let mut handle = Handle { out: String::new(), pen_ops: 0, pen_hash: FNV_OFFSET };
+    world.broadcast_toplevel_async(Trigger::FlagClicked);
+    let mut ticks = 0;
+    while !world.is_finished() && ticks < max_ticks {
+        // There are no frames so cloud variables sync around every poll.
+        #[cfg(feature = "cloud")]
+        world.cloud_receive();
+        let progress = world.poll(&mut handle);
+        #[cfg(feature = "cloud")]
+        world.cloud_send();
+        if !progress {
+            // Everything is waiting on a timer so skip ahead.
+            clock::end_frame();
+        }
+        ticks += 1;
+    }
+
+    let out = &mut handle.out;
+    if !world.is_finished() {
+        writeln!(out, "timeout after {max_ticks} ticks").unwrap();
+    }
+    writeln!(out, "pen {} ops {:016x}", handle.pen_ops, handle.pen_hash).unwrap();
+    write_vars::<S>(out, "global", &world.globals);
+    for (i, (base, custom)) in world.bases.iter().zip(world.custom.iter()).enumerate() {
+        writeln!(out, "sprite {i} at ({}, {}) costume {} hidden {}", base.x, base.y, base.costume, base.hidden).unwrap();
+        write_vars::<S>(out, &format!("sprite {i}"), &**custom);
+    }
+    handle.out
+}
+
+/// Sorted by name since the order of fields isn't stable between compiles.
+fn write_vars<S: ScratchProgram<BackendImpl<S>>>(out: &mut String, owner: &str, sprite: &dyn Sprite<S, BackendImpl<S>>) {
+    let mut vars: Vec<_> = sprite.get_var_names().iter().enumerate().map(|(i, name)| (name, sprite.var(i).to_string())).collect();
+    vars.sort();
+    for (name, value) in vars {
+        writeln!(out, "{owner} {name:?} = {value}").unwrap();
+    }
+}
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+impl Handle {
+    fn pen(&mut self, op: Arguments) {
+        self.pen_ops += 1;
+        for b in op.to_string().bytes() {
+            self.pen_hash = (self.pen_hash ^ b as u64).wrapping_mul(FNV_PRIME);
+        }
+    }
+}
+
+impl RenderHandle for Handle {
+    fn pen_line(&mut self, line: Line) {
+        self.pen(format_args!("line {:?} {:?} {} {:x}", line.start, line.end, line.size, line.colour.0));
+    }
+
+    fn pen_stamp(&mut self, pos: (f64, f64), costume: usize, size: f64, direction: f64) {
+        self.pen(format_args!("stamp {pos:?} {costume} {size} {direction}"));
+    }
+
+    fn say(&mut self, text: &str, pos: (f64, f64)) {
+        writeln!(self.out, "say {text:?} at {pos:?}").unwrap();
+    }
+
+    fn save_frame(&mut self, _: &str) {}
+
+    fn pen_clear(&mut self) {
+        self.pen(format_args!("clear"));
+    }
+}
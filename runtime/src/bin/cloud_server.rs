@@ -0,0 +1,8 @@
+//! Shares cloud variables between programs on this machine or network. Values only live as long as the server.
+//! cargo run -p runtime --features cloud --bin cloud_server -- 127.0.0.1:9901
+//! Then start each program with --cloud-server 127.0.0.1:9901
+
+fn main() {
+    let addr = std::env::args().nth(1).unwrap_or_else(|| String::from("127.0.0.1:9901"));
+    runtime::cloud::serve(&addr)
+}
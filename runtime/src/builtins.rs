@@ -2,29 +2,32 @@
 
 use std::any::{Any};
 use rand::{Rng, SeedableRng};
-use rand::rngs::{StdRng, ThreadRng};
+use rand::rngs::ThreadRng;
+use rand_chacha::ChaCha12Rng;
 use std::cell::RefCell;
-use std::io::{stdout, Write};
 use std::ops::Add;
 use std::time::{Duration, SystemTime};
 use crate::backend::RenderBackend;
 use crate::poly::Str;
-use crate::{Poly, RenderHandle, ScratchProgram, Sprite};
+use crate::{clock, Config, RenderHandle, RunMode, ScratchProgram, Sprite};
 use crate::sprite::{Line, SpriteBase};
-
-pub const HALF_SCREEN_WIDTH: f64 = 240.0;
-pub const HALF_SCREEN_HEIGHT: f64 = 180.0;
+use crate::backend::pen::{MAX_PEN_SIZE, MIN_PEN_SIZE};
 
 
 // Note: each instance is linked to a specific sprite.
 pub struct FrameCtx<'msg, 'frame: 'msg, S: ScratchProgram<R>, R: RenderBackend<S>> {
     pub sprite: &'msg mut SpriteBase,
+    /// Index into ScratchProgram::create_initial_state's sprites.
+    pub(crate) owner: usize,
     // pub vars: &'a mut S,
     pub globals: &'msg mut S::Globals,
     pub(crate) render: &'msg mut R::Handle<'frame>,
+    /// See World::poll_scratch.
+    pub(crate) redraw: &'msg mut bool,
+    pub(crate) config: &'msg Config,
+    pub(crate) mode: RunMode,
 }
 
-// TODO: think about some macro magic to generate the prototypes in the compiler based on these functions.
 impl<'msg, 'frame: 'msg, S: ScratchProgram<R>, R: RenderBackend<S>> FrameCtx<'msg, 'frame, S, R> {
     // TODO: check if using downcast_mut_unchecked is faster (it still asserts in debug builds)
     // TODO: why can you only call downcast_mut on dyn Any not dyn <some trait: Any>. https://github.com/rust-lang/rust/issues/65991
@@ -35,12 +38,19 @@ impl<'msg, 'frame: 'msg, S: ScratchProgram<R>, R: RenderBackend<S>> FrameCtx<'ms
         sprite.downcast_mut().unwrap()
     }
 
+    /// Checked by the compiled code at the end of every loop iteration. Only RunMode::Scratch yields there,
+    /// the other modes keep going until the script waits on something. Run without screen refresh procedures don't check.
+    pub fn loop_yields(&self) -> bool {
+        self.mode == RunMode::Scratch
+    }
+
     pub fn pen_setPenColorToColor(&mut self, colour: f64) {
         self.sprite.pen.colour.0 = colour.max(0.0) as u32;
     }
 
     pub fn pen_setPenSizeTo(&mut self, size: f64) {
-        self.sprite.pen.size = size;
+        // Renderers still won't draw smaller than MIN_PEN_SIZE.
+        self.sprite.pen.size = if self.config.misc_limits { size.clamp(MIN_PEN_SIZE, MAX_PEN_SIZE) } else { size };
     }
 
     pub fn pen_penUp(&mut self) {
@@ -53,32 +63,32 @@ impl<'msg, 'frame: 'msg, S: ScratchProgram<R>, R: RenderBackend<S>> FrameCtx<'ms
 
     pub fn motion_changexby(&mut self, dx: f64) {
         let old = self.pos();
-        self.sprite.x = (self.sprite.x + dx).clamp(-HALF_SCREEN_WIDTH, HALF_SCREEN_WIDTH);
+        self.sprite.x = self.fence_x(self.sprite.x + dx);
         self.draw(old);
     }
 
     pub fn motion_changeyby(&mut self, dy: f64) {
         let old = self.pos();
-        self.sprite.y = (self.sprite.y + dy).clamp(-HALF_SCREEN_HEIGHT, HALF_SCREEN_HEIGHT);
+        self.sprite.y = self.fence_y(self.sprite.y + dy);
         self.draw(old);
     }
 
     pub fn motion_setx(&mut self, x: f64) {
         let old = self.pos();
-        self.sprite.x = x.clamp(-HALF_SCREEN_WIDTH, HALF_SCREEN_WIDTH);
+        self.sprite.x = self.fence_x(x);
         self.draw(old);
     }
 
     pub fn motion_sety(&mut self, y: f64) {
         let old = self.pos();
-        self.sprite.y = y.clamp(-HALF_SCREEN_HEIGHT, HALF_SCREEN_HEIGHT);
+        self.sprite.y = self.fence_y(y);
         self.draw(old);
     }
 
     pub fn motion_gotoxy(&mut self, x: f64, y: f64) {
         let old = self.pos();
-        self.sprite.x = x.clamp(-HALF_SCREEN_WIDTH, HALF_SCREEN_WIDTH);
-        self.sprite.y = y.clamp(-HALF_SCREEN_HEIGHT, HALF_SCREEN_HEIGHT);
+        self.sprite.x = self.fence_x(x);
+        self.sprite.y = self.fence_y(y);
         self.draw(old);
     }
 
@@ -91,105 +101,176 @@ impl<'msg, 'frame: 'msg, S: ScratchProgram<R>, R: RenderBackend<S>> FrameCtx<'ms
     }
 
     pub fn sensing_answer(&self) -> Str {
-        Str::Owned(self.sprite.last_answer.clone())
+        Str::from(self.sprite.last_answer.clone())
     }
 
     pub fn pen_stamp(&mut self) {
         // TODO: make sure this uses sprite size not pen size
-        self.render.pen_stamp(self.pos(), self.sprite.costume, self.sprite.size_frac);
+        self.render.pen_stamp(self.pos(), self.sprite.costume, self.sprite.size_frac, self.sprite.direction);
+        *self.redraw = true;
     }
 
     pub fn looks_hide(&mut self) {
+        self.request_redraw();
         self.sprite.hidden = true;
     }
 
     pub fn pen_clear(&mut self) {
         self.render.pen_clear();
+        *self.redraw = true;
     }
 
     pub fn looks_setsizeto(&mut self, size: f64) {
         // TODO: what are the limits?
         self.sprite.size_frac = size / 100.0;
+        self.request_redraw();
     }
 
-    // TODO: each sprite has its own set of costumes and they can have overlapping names so really it has to pass in the id and costume_by_name needs to be on the sprite trait
     pub fn looks_switchcostumeto(&mut self, costume: Str) {
-        if let Some(id) = S::costume_by_name(costume.clone()) {
-            self.sprite.costume = id;
-        }
-
-        match costume {
-            Str::Char(c) => {
-                print!("{c}");
-                stdout().flush().unwrap();
-            },
-            Str::Const(_) | Str::Owned(_) => {},
+        let (first, names) = S::costume_names()[self.owner];
+        if let Some(index) = costume_index(names, costume.as_ref()) {
+            self.sprite.costume = first + index;
+            self.request_redraw();
         }
     }
 
-    // TODO: remove
-    pub fn sensing_askandwait_sync(&mut self) {
-        let mut line = String::new();
-        std::io::stdin().read_line(&mut line).unwrap();
-        self.sprite.last_answer = line;  // TODO: trim new-line?
+    /// looks_switchcostumeto with a constant name already resolved by the compiler.
+    pub fn looks_switchcostumeto_id(&mut self, costume: i64) {
+        self.sprite.costume = costume as usize;
+        self.request_redraw();
     }
 
     pub fn looks_say(&mut self, msg: Str) {
-        println!("[SAY] {msg:?}");  // TODO: dont print.
         self.render.say(msg.as_ref(), self.pos());
-    }
-
-    pub fn stop_all_sync(&mut self) {
-        println!("stop all");
-        self.render.save_frame(&format!("scratch_exit_{}.png", SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs()));
-        std::process::exit(0);
+        self.request_redraw();
     }
 
     pub fn control_create_clone_of(&self) {
         println!("TODO: clone-myself")
     }
 
+    // TODO: scratch fences the costume's bounds not the center.
+    fn fence_x(&self, x: f64) -> f64 {
+        if self.config.fencing { x.clamp(-self.config.half_width(), self.config.half_width()) } else { x }
+    }
+
+    fn fence_y(&self, y: f64) -> f64 {
+        if self.config.fencing { y.clamp(-self.config.half_height(), self.config.half_height()) } else { y }
+    }
+
     fn pos(&self) -> (f64, f64) {
         (self.sprite.x, self.sprite.y)
     }
 
+    /// Scratch only ends a tick early for changes you can see.
+    fn request_redraw(&mut self) {
+        if !self.sprite.hidden {
+            *self.redraw = true;
+        }
+    }
+
     fn draw(&mut self, old: (f64, f64)) {
+        self.request_redraw();
         if self.sprite.pen.active {
-            let end = self.pos();
-            let is_pixel = self.sprite.pen.size <= 1.0 && 1.0 == ((old.0 - end.0).powi(2) + (old.1 - end.1).powi(2)) ;
-            if is_pixel {
-                self.render.pen_pixel(end, self.sprite.pen.colour);
-            } else {
-                self.render.pen_line(Line {
-                    start: old,
-                    end,
-                    size: self.sprite.pen.size,
-                    colour: self.sprite.pen.colour,
-                })
-            }
+            *self.redraw = true;
+            self.render.pen_line(Line {
+                start: old,
+                end: self.pos(),
+                size: self.sprite.pen.size,
+                colour: self.sprite.pen.colour,
+            })
         }
     }
 
     pub fn sensing_dayssince2000(&self) -> f64 {
         const SECS_PER_DAY: f64 = 86400.0;
-        const SECS_OFFSET: u64 = (SECS_PER_DAY * 365.25 * 30.0) as u64;  // TODO
+        const SECS_OFFSET: u64 = 946684800;  // 2000-01-01 00:00 UTC
 
         let epoch = SystemTime::UNIX_EPOCH.add(Duration::from_secs(SECS_OFFSET));
-        let since = SystemTime::now().duration_since(epoch).unwrap();
+        let since = clock::system_now().duration_since(epoch).unwrap();
         since.as_secs_f64() / SECS_PER_DAY
     }
 }
 
+/// Fails to build if the signatures in builtin_registry! don't match the methods above.
+macro_rules! registry {
+    (@ty Number) => { f64 };
+    (@ty Integer) => { i64 };
+    (@ty Str) => { Str };
+    (@ty Bool) => { bool };
+    (@ty Poly) => { crate::poly::Poly };
+    (@ret) => { () };
+    (@ret $t:ident) => { registry!(@ty $t) };
+    ($($name:ident($($arg:ident: $t:ident),*) $(-> $ret:ident)?;)*) => {
+        #[allow(dead_code)]
+        fn check_registry<S: ScratchProgram<R>, R: RenderBackend<S>>() {
+            $(
+                let _ = |ctx: &mut FrameCtx<S, R>, $($arg: registry!(@ty $t)),*| -> registry!(@ret $($ret)?) { ctx.$name($($arg),*) };
+            )*
+        }
+    };
+}
+
+crate::builtin_registry!(registry);
+
 thread_local! {
-    static RNG: RefCell<StdRng> = RefCell::new(StdRng::from_rng(ThreadRng::default()).unwrap());
+    static RNG: RefCell<ChaCha12Rng> = RefCell::new(ChaCha12Rng::from_rng(ThreadRng::default()).unwrap());
+}
+
+/// Use World::set_deterministic instead.
+pub(crate) fn seed_rand(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = ChaCha12Rng::seed_from_u64(seed));
+}
+
+/// Enough to continue the same sequence of random numbers after loading a save state.
+#[cfg(feature = "save")]
+pub(crate) fn rand_state() -> ([u8; 32], u64, u128) {
+    RNG.with(|rng| {
+        let rng = rng.borrow();
+        (rng.get_seed(), rng.get_stream(), rng.get_word_pos())
+    })
+}
+
+#[cfg(feature = "save")]
+pub(crate) fn set_rand_state((seed, stream, word_pos): ([u8; 32], u64, u128)) {
+    RNG.with(|rng| {
+        let mut rng = rng.borrow_mut();
+        *rng = ChaCha12Rng::from_seed(seed);
+        rng.set_stream(stream);
+        rng.set_word_pos(word_pos);
+    })
 }
 
 pub fn dyn_rand(min: f64, max: f64) -> f64 {
     if min.round() == min && max.round() == max {
         // If both sides are whole numbers, result is a whole number.
-        // TODO: make my compiler constant fold this branch
-        RNG.with(|rng| rng.borrow_mut().gen_range((min as isize)..(max as isize)) as f64)
+        rand_int(min, max)
     } else {
-        RNG.with(|rng| rng.borrow_mut().gen_range(min..max))
+        rand_float(min, max)
+    }
+}
+
+/// dyn_rand when the compiler knows both bounds are whole numbers.
+pub fn rand_int(min: f64, max: f64) -> f64 {
+    RNG.with(|rng| rng.borrow_mut().gen_range((min as isize)..(max as isize)) as f64)
+}
+
+pub fn rand_float(min: f64, max: f64) -> f64 {
+    RNG.with(|rng| rng.borrow_mut().gen_range(min..max))
+}
+
+/// Which of a sprite's costumes switch costume picks. Like Scratch, its own costume with that name,
+/// otherwise a number is a position in the list starting at 1 (wrapping around). The compiler resolves constant names with this too.
+pub fn costume_index(names: &[&str], name: &str) -> Option<usize> {
+    if let Some(index) = names.iter().position(|c| *c == name) {
+        return Some(index);
+    }
+    let count = names.len() as f64;
+    match name.trim().parse::<f64>() {
+        Ok(n) if count > 0.0 && !n.is_nan() => {
+            let index = if n.is_finite() { (n.round() - 1.0).rem_euclid(count) } else { 0.0 };
+            Some(index as usize)
+        }
+        _ => None,
     }
 }
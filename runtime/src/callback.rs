@@ -13,6 +13,8 @@ use std::time::{Duration};
 use crate::{FrameCtx, RenderBackend, ScratchProgram, Trigger, World};
 use crate::sprite::{Sprite, SpriteBase};
 use crate::Instant;
+#[cfg(feature = "save")]
+use crate::save::SaveWriter;
 
 // TODO: try to clean up the concurrency model. Relationship between IoAction, FnFut, and FutOut feels a bit over complicated.
 pub enum IoAction<S: ScratchProgram<R>, R: RenderBackend<S>> {
@@ -25,15 +27,19 @@ pub enum IoAction<S: ScratchProgram<R>, R: RenderBackend<S>> {
     BroadcastWait(S::Msg),
     CloneMyself,  // TODO: impl
     LoopYield,
-    StopAllScripts,  // TODO: is this just on one sprite?
-    /// Pop back up the closest CallMarker
+    /// Ends every script in the program (see World::stop_all).
+    StopAllScripts,
+    /// Returned by a FutMachine to end itself without returning to its caller, so a custom block returns and a script finishes.
     StopCurrentScript,
+    /// Ends every other script of the same sprite. Resolves instantly.
+    StopOtherScripts,
     None,
-    // TODO: This should be a Vec<Script> instead since you might want to wait on other sprites.
-    //       then receive should return a vec![ioaction] and the runtime turns it into one of these or flattens it.
-    //       then the top level of the runtime can also be one of these and everything's consistant.
+    /// Several scripts of this sprite started by one trigger. Resolves to ConcurrentScripts.
     Concurrent(Vec<IoAction<S, R>>),
+    /// Starts each as its own script (in its owner's context) and resolves to WaitForScripts.
     ConcurrentScripts(Vec<Script<S, R>>),
+    /// Resolves when every script started by that ConcurrentScripts has finished.
+    WaitForScripts(usize),
     // TODO: this biggest variant (5 words), can i cut it down? name can be an index.
     //       im sure u16 is enough for both but rust might word align things anyway
     /// An iterator yielding IoActions.
@@ -46,16 +52,34 @@ pub type DbgId = usize;
 #[cfg(not(feature = "inspect"))]
 pub type DbgId = ();
 
-pub type FutMachine<S, R> = dyn FnMut(&mut FrameCtx<S, R>, &mut dyn Any, NonZeroU16) -> FutRes<S, R>;
+pub type FutMachine<S, R> = dyn Machine<S, R>;
 // Storing the state outside in the runtime means functions with no args (and no loops) don't need to allocate.
 // And that the debugger can show where you are in the function.
 pub type FutRes<S, R> = (IoAction<S, R>, Option<NonZeroU16>);
 
+/// A function split at its await points. The compiler generates a struct for each script and each custom block that waits.
+/// Locals (arguments and loop counters) are fields instead of closure captures so save states can write them.
+pub trait Machine<S: ScratchProgram<R>, R: RenderBackend<S>>: 'static {
+    /// Runs from state to the next await point.
+    fn resume(&mut self, ctx: &mut FrameCtx<S, R>, this: &mut dyn Any, state: NonZeroU16) -> FutRes<S, R>;
+
+    /// Writes the id ScratchProgram::load_machine expects and then the locals.
+    #[cfg(feature = "save")]
+    fn save(&self, _out: &mut SaveWriter) {
+        panic!("This script can't be saved");
+    }
+}
+
 #[derive(Debug)]
 pub struct Script<S: ScratchProgram<R>, R: RenderBackend<S>>  {
     pub next: Vec<IoAction<S, R>>,
     pub owner: usize,  // Which instance requested this action
-    pub trigger: Trigger<S::Msg> // Shown in debugger
+    pub trigger: Trigger<S::Msg>, // Shown in debugger
+    /// Set if it was started by a ConcurrentScripts whose script is waiting for it.
+    pub group: Option<usize>,
+    /// Which of the owner's scripts for trigger this is (see Sprite::receive_async). None if a hat didn't start it.
+    /// Like Scratch, a hat that fires while its script is still running restarts it instead of starting a second copy.
+    pub hat: Option<usize>,
 }
 
 // TODO: this is unnecessary but makes me feel better
@@ -85,7 +109,9 @@ impl<S: ScratchProgram<R>, R: RenderBackend<S>> Debug for IoAction<S, R> {
             IoAction::SleepSecs(s) => write!(f, "StartSleep({s})"),
             IoAction::StopAllScripts => write!(f, "StopAllScripts"),
             IoAction::StopCurrentScript => write!(f, "StopCurrentScript"),
+            IoAction::StopOtherScripts => write!(f, "StopOtherScripts"),
             IoAction::ConcurrentScripts(s) => write!(f, "ConcurrentScripts(...)"),
+            IoAction::WaitForScripts(group) => write!(f, "WaitForScripts({group})"),
             IoAction::FutMachine(_, name, state) => write!(f, "{name} [{state}]"),
         }
     }
@@ -100,19 +126,19 @@ macro_rules! state {
     }};
 }
 
-pub fn fut<S, R, F>(name: &'static str, f: F) -> IoAction<S, R>
+pub fn fut<S, R, M>(name: &'static str, machine: M) -> IoAction<S, R>
     where S: ScratchProgram<R>,
           R: RenderBackend<S>,
-          F: FnMut(&mut FrameCtx<S, R>, &mut dyn Any, NonZeroU16) -> FutRes<S, R> + 'static
+          M: Machine<S, R>
 {
-    assert_zero_sized::<F>();
-    IoAction::FutMachine(Box::new(f), name, state!(1))
+    assert_zero_sized::<M>();
+    IoAction::FutMachine(Box::new(machine), name, state!(1))
 }
 
-pub fn fut_a<S, R, F>(name: &'static str, f: F) -> IoAction<S, R>
+pub fn fut_a<S, R, M>(name: &'static str, machine: M) -> IoAction<S, R>
     where S: ScratchProgram<R>,
           R: RenderBackend<S>,
-          F: FnMut(&mut FrameCtx<S, R>, &mut dyn Any, NonZeroU16) -> FutRes<S, R> + 'static
+          M: Machine<S, R>
 {
-    IoAction::FutMachine(Box::new(f), name, state!(1))
+    IoAction::FutMachine(Box::new(machine), name, state!(1))
 }
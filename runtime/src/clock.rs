@@ -0,0 +1,68 @@
+//! Every read of the time goes through here so it can be swapped for a virtual clock.
+//! In deterministic mode time only moves when a frame ends, so the same project with the same seed does the same thing every run.
+
+use std::cell::Cell;
+use std::ops::Add;
+use std::time::{Duration, SystemTime};
+use crate::Instant;
+
+/// How far the virtual clock moves each frame.
+pub const FRAME_STEP: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+/// The virtual clock pretends the program started at 2024-01-01 00:00 UTC.
+const VIRTUAL_EPOCH_SECS: u64 = 1704067200;
+
+#[derive(Copy, Clone)]
+struct Virtual {
+    start: Instant,
+    elapsed: Duration,
+}
+
+thread_local! {
+    static VIRTUAL: Cell<Option<Virtual>> = const { Cell::new(None) };
+}
+
+pub fn now() -> Instant {
+    match VIRTUAL.get() {
+        None => Instant::now(),
+        Some(v) => v.start.add(v.elapsed),
+    }
+}
+
+pub fn system_now() -> SystemTime {
+    match VIRTUAL.get() {
+        None => SystemTime::now(),
+        Some(v) => SystemTime::UNIX_EPOCH.add(Duration::from_secs(VIRTUAL_EPOCH_SECS)).add(v.elapsed),
+    }
+}
+
+pub fn is_deterministic() -> bool {
+    VIRTUAL.get().is_some()
+}
+
+/// Resets the virtual clock to zero. Called by World::set_deterministic which also seeds the rng.
+pub(crate) fn start_virtual() {
+    VIRTUAL.set(Some(Virtual { start: Instant::now(), elapsed: Duration::ZERO }));
+}
+
+/// How far the virtual clock has moved. None when using the real clock.
+pub fn elapsed() -> Option<Duration> {
+    VIRTUAL.get().map(|v| v.elapsed)
+}
+
+/// Puts the virtual clock back where a save state left it. Does nothing when using the real clock.
+#[cfg(feature = "save")]
+pub(crate) fn set_elapsed(elapsed: Duration) {
+    if let Some(mut v) = VIRTUAL.get() {
+        v.elapsed = elapsed;
+        VIRTUAL.set(Some(v));
+    }
+}
+
+/// Moves the virtual clock forward one frame. Does nothing when using the real clock.
+pub fn end_frame() {
+    if let Some(mut v) = VIRTUAL.get() {
+        v.elapsed += FRAME_STEP;
+        VIRTUAL.set(Some(v));
+    }
+}
@@ -0,0 +1,324 @@
+//! Cloud variables: globals shared with every other copy of the program (high score tables, multiplayer).
+//! World sends a cloud variable to the provider at the end of any frame where a script changed it
+//! and writes values the provider heard about from elsewhere at the start of the next frame.
+//!
+//! The generated exe takes --cloud-server <addr> to share them through a cloud_server (src/bin/cloud_server.rs) over tcp.
+//! Otherwise they're kept in --cloud-file <path> (default cloud.json) between runs.
+//! Both speak the same json as Scratch's cloud server: one object per line like {"method":"set","name":"☁ score","value":"10"}.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::env::args;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use serde_json::{json, Value};
+use crate::{Poly, RenderBackend, ScratchProgram, Sprite, Str, VarBorrow, VarBorrowMut};
+
+/// Where cloud variables are stored. Values are strings on the wire like in Scratch.
+pub trait CloudProvider {
+    /// A script in this program changed a cloud variable.
+    fn set(&mut self, name: &str, value: &str);
+
+    /// Values that changed somewhere else since the last call. The first call returns everything already stored.
+    fn poll(&mut self) -> Vec<(String, String)>;
+}
+
+/// The --cloud-server or --cloud-file passed to the exe.
+pub fn provider_from_args() -> Box<dyn CloudProvider> {
+    match args().skip_while(|arg| arg != "--cloud-server").nth(1) {
+        Some(addr) => Box::new(CloudSocket::connect(&addr)),
+        None => Box::new(CloudFile::open(&args().skip_while(|arg| arg != "--cloud-file").nth(1).unwrap_or_else(|| String::from("cloud.json")))),
+    }
+}
+
+/// Keeps values in a json object on disk so they survive between runs. Not shared between programs running at the same time.
+pub struct CloudFile {
+    path: String,
+    values: BTreeMap<String, String>,
+    loaded: bool,
+}
+
+impl CloudFile {
+    pub fn open(path: &str) -> CloudFile {
+        let values = match fs::read_to_string(path) {
+            Ok(src) => serde_json::from_str(&src).unwrap_or_else(|e| panic!("Bad cloud file {path}: {e}")),
+            Err(e) if e.kind() == ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => panic!("Failed to read cloud file {path}: {e}"),
+        };
+        CloudFile { path: path.to_string(), values, loaded: false }
+    }
+}
+
+impl CloudProvider for CloudFile {
+    /// Writes the whole file every time. World only calls this once per frame for each variable that changed.
+    fn set(&mut self, name: &str, value: &str) {
+        self.values.insert(name.to_string(), value.to_string());
+        let src = serde_json::to_string_pretty(&self.values).unwrap();
+        fs::write(&self.path, src).unwrap_or_else(|e| panic!("Failed to write cloud file {}: {e}", self.path));
+    }
+
+    fn poll(&mut self) -> Vec<(String, String)> {
+        if self.loaded {
+            return vec![];
+        }
+        self.loaded = true;
+        self.values.iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+    }
+}
+
+/// Shares values with every other program connected to the same server.
+/// If the connection drops the program keeps running with whatever values it has.
+pub struct CloudSocket {
+    stream: Option<TcpStream>,
+    /// Bytes read but not yet a whole line.
+    input: Vec<u8>,
+    /// Bytes not written yet because the socket would block. Retried every poll.
+    output: Vec<u8>,
+}
+
+/// How long connect waits for the server to send the stored values before the first frame.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_millis(250);
+
+impl CloudSocket {
+    /// Blocks until the server has sent the stored values so scripts see them on the first frame.
+    pub fn connect(addr: &str) -> CloudSocket {
+        let stream = TcpStream::connect(addr).unwrap_or_else(|e| panic!("Failed to connect to cloud server {addr}: {e}"));
+        stream.set_nodelay(true).unwrap();
+        let mut socket = CloudSocket { stream: Some(stream), input: vec![], output: vec![] };
+        socket.send(json!({"method": "handshake"}));
+
+        // The protocol doesn't say when the server is done so wait until it goes quiet.
+        let stream = socket.stream.as_ref().unwrap();
+        stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT)).unwrap();
+        socket.read();
+        if let Some(stream) = &socket.stream {
+            stream.set_read_timeout(None).unwrap();
+            stream.set_nonblocking(true).unwrap();
+        }
+        socket
+    }
+
+    fn send(&mut self, msg: Value) {
+        if self.stream.is_some() {
+            writeln!(self.output, "{msg}").unwrap();
+            self.flush();
+        }
+    }
+
+    /// Writes until the socket would block. The rest stays in output for next time.
+    fn flush(&mut self) {
+        let Some(stream) = &mut self.stream else { return };
+        while !self.output.is_empty() {
+            match stream.write(&self.output) {
+                Ok(0) => {
+                    eprintln!("Cloud server closed the connection");
+                    self.stream = None;
+                    return;
+                }
+                Ok(n) => drop(self.output.drain(..n)),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    eprintln!("Lost connection to cloud server: {e}");
+                    self.stream = None;
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Reads until the socket would block.
+    fn read(&mut self) {
+        let Some(stream) = &mut self.stream else { return };
+        let mut buf = [0; 4096];
+        loop {
+            match stream.read(&mut buf) {
+                Ok(0) => {
+                    eprintln!("Cloud server closed the connection");
+                    self.stream = None;
+                    return;
+                }
+                Ok(n) => self.input.extend_from_slice(&buf[..n]),
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => return,
+                Err(e) => {
+                    eprintln!("Lost connection to cloud server: {e}");
+                    self.stream = None;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl CloudProvider for CloudSocket {
+    fn set(&mut self, name: &str, value: &str) {
+        self.send(json!({"method": "set", "name": name, "value": value}));
+    }
+
+    fn poll(&mut self) -> Vec<(String, String)> {
+        self.flush();
+        self.read();
+        let end = self.input.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+        let lines: Vec<u8> = self.input.drain(..end).collect();
+        lines.split(|&b| b == b'\n').filter_map(|line| parse_set(&serde_json::from_slice(line).ok()?)).collect()
+    }
+}
+
+/// The name and value of a set message. Anything else is ignored.
+fn parse_set(msg: &Value) -> Option<(String, String)> {
+    if msg["method"] != "set" {
+        return None;
+    }
+    let value = match &msg["value"] {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    };
+    Some((msg["name"].as_str()?.to_string(), value))
+}
+
+/// Stand-in for Scratch's cloud server. Remembers the latest value of every variable,
+/// sends them all to each program that connects, and forwards every set to the other programs.
+pub fn serve(addr: &str) -> ! {
+    let listener = TcpListener::bind(addr).unwrap_or_else(|e| panic!("Failed to listen on {addr}: {e}"));
+    eprintln!("Cloud server listening on {addr}");
+    serve_listener(listener)
+}
+
+/// Like serve but on a listener that's already bound (ie. to port 0 in tests).
+pub fn serve_listener(listener: TcpListener) -> ! {
+    let server: Arc<Mutex<Server>> = Default::default();
+    for (id, stream) in listener.incoming().enumerate() {
+        let Ok(stream) = stream else { continue };
+        let server = server.clone();
+        thread::spawn(move || serve_client(&server, id, stream));
+    }
+    unreachable!("TcpListener::incoming never ends")
+}
+
+/// How long a program gets to read a message before the server gives up on it.
+const CLIENT_WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Default)]
+struct Server {
+    values: BTreeMap<String, String>,
+    /// Lines queued here are written by that client's thread in write_client so a slow program can't block the rest.
+    clients: Vec<(usize, Sender<String>)>,
+}
+
+fn serve_client(server: &Mutex<Server>, id: usize, stream: TcpStream) {
+    let Ok(output) = stream.try_clone() else { return };
+    let mut joined = false;
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else { break };
+        let Ok(msg) = serde_json::from_str::<Value>(&line) else { continue };
+        let mut server = server.lock().unwrap();
+        if let Some((name, value)) = parse_set(&msg) {
+            let msg = json!({"method": "set", "name": name, "value": value}).to_string();
+            server.values.insert(name, value);
+            // Programs whose writer gave up are dropped.
+            server.clients.retain(|(other, out)| *other == id || out.send(msg.clone()).is_ok());
+        } else if msg["method"] == "handshake" && !joined {
+            let (send, receive) = mpsc::channel();
+            for (name, value) in &server.values {
+                let _ = send.send(json!({"method": "set", "name": name, "value": value}).to_string());
+            }
+            server.clients.push((id, send));
+            joined = true;
+            let Ok(output) = output.try_clone() else { break };
+            thread::spawn(move || write_client(output, receive));
+        }
+    }
+    server.lock().unwrap().clients.retain(|(other, _)| *other != id);
+}
+
+/// Ends when the program disconnects or stops reading for CLIENT_WRITE_TIMEOUT.
+/// That drops the receiver so the next send to it fails and serve_client forgets it.
+fn write_client(mut output: TcpStream, lines: Receiver<String>) {
+    if output.set_write_timeout(Some(CLIENT_WRITE_TIMEOUT)).is_err() {
+        return;
+    }
+    for line in lines {
+        if writeln!(output, "{line}").is_err() {
+            let _ = output.shutdown(Shutdown::Both);
+            return;
+        }
+    }
+}
+
+/// Connects World's globals to a provider.
+pub(crate) struct CloudSync {
+    provider: Box<dyn CloudProvider>,
+    /// Indexes of the cloud variables in the globals' var list (see ScratchProgram::cloud_vars).
+    vars: &'static [usize],
+    /// What each variable was when it was last sent or received so unchanged values aren't sent again.
+    synced: Vec<Option<String>>,
+}
+
+impl CloudSync {
+    /// Starts with the current values as synced so the defaults don't overwrite what's stored.
+    pub(crate) fn new<S: ScratchProgram<R>, R: RenderBackend<S>>(provider: Box<dyn CloudProvider>, globals: &S::Globals) -> Self {
+        let vars = S::cloud_vars();
+        let synced = vars.iter().map(|&i| cloud_text(globals.var(i))).collect();
+        CloudSync { provider, vars, synced }
+    }
+
+    /// Unknown names are ignored. They belong to a different version of the program.
+    pub(crate) fn receive<S: ScratchProgram<R>, R: RenderBackend<S>>(&mut self, globals: &mut S::Globals) {
+        let names = globals.get_var_names();
+        for (name, value) in self.provider.poll() {
+            let Some(slot) = self.vars.iter().position(|&i| names[i] == name) else { continue };
+            set_cloud_text(globals.var_mut(self.vars[slot]), &value);
+            self.synced[slot] = cloud_text(globals.var(self.vars[slot]));
+        }
+    }
+
+    /// Puts the last shared values back after World::restart resets the globals, so the defaults don't get sent.
+    pub(crate) fn restore<S: ScratchProgram<R>, R: RenderBackend<S>>(&self, globals: &mut S::Globals) {
+        for (slot, &i) in self.vars.iter().enumerate() {
+            if let Some(value) = &self.synced[slot] {
+                set_cloud_text(globals.var_mut(i), value);
+            }
+        }
+    }
+
+    pub(crate) fn send<S: ScratchProgram<R>, R: RenderBackend<S>>(&mut self, globals: &S::Globals) {
+        let names = globals.get_var_names();
+        for (slot, &i) in self.vars.iter().enumerate() {
+            let value = cloud_text(globals.var(i));
+            if value != self.synced[slot] {
+                if let Some(value) = &value {
+                    self.provider.set(names[i], value);
+                }
+                self.synced[slot] = value;
+            }
+        }
+    }
+}
+
+/// Scratch only allows numbers in cloud variables. The compiler always stores them as Number but other types still work.
+fn cloud_text(var: VarBorrow) -> Option<String> {
+    match var {
+        VarBorrow::Num(n) => Some(Poly::Num(*n).as_str().as_ref().to_string()),
+        VarBorrow::Int(n) => Some(n.to_string()),
+        VarBorrow::Bool(b) => Some(b.to_string()),
+        VarBorrow::Str(s) => Some(s.as_ref().to_string()),
+        VarBorrow::Poly(p) => Some(p.as_str().as_ref().to_string()),
+        _ => None,
+    }
+}
+
+fn set_cloud_text(var: VarBorrowMut, value: &str) {
+    let poly = Poly::Str(Str::from(value.to_string()));
+    match var {
+        VarBorrowMut::Num(n) => *n = poly.as_num(),
+        VarBorrowMut::Int(n) => *n = poly.as_num() as i64,
+        VarBorrowMut::Bool(b) => *b = value == "true",
+        VarBorrowMut::Str(s) => *s = poly.into(),
+        VarBorrowMut::Poly(p) => *p = poly,
+        _ => {}
+    }
+}
@@ -0,0 +1,88 @@
+//! Project settings. TurboWarp saves them in a comment on the stage and the compiler copies them into ScratchProgram::config.
+//! Flags passed to the exe override them. They're named like TurboWarp's url parameters:
+//! --fps <n>, --turbo, --interpolate, --hqpen, --offscreen (no fencing), --limitless (no misc limits or clone limit),
+//! --clones <n> and --size <width>x<height>.
+
+use std::env::args;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    /// Ticks per second in RunMode::Scratch. 0 means match the screen, which is 60 on every backend here.
+    pub framerate: u32,
+    /// Start in RunMode::Turbo.
+    pub turbo: bool,
+    // TODO: draw sprites between ticks.
+    pub interpolation: bool,
+    // TODO: draw the pen layer at window resolution.
+    pub hq_pen: bool,
+    /// Keep sprites on the stage.
+    pub fencing: bool,
+    // TODO: clones aren't implemented yet.
+    pub max_clones: usize,
+    /// Clamp pen size to what Scratch allows.
+    pub misc_limits: bool,
+    /// Stage size in pixels. Scratch coordinates go from -width/2 to width/2.
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Default for Config {
+    /// What Scratch does.
+    fn default() -> Self {
+        Config {
+            framerate: 30,
+            turbo: false,
+            interpolation: false,
+            hq_pen: false,
+            fencing: true,
+            max_clones: 300,
+            misc_limits: true,
+            width: 480,
+            height: 360,
+        }
+    }
+}
+
+impl Config {
+    /// Applies the exe's flags.
+    pub fn with_args(mut self) -> Self {
+        let value = |flag: &str| args().skip_while(|arg| arg != flag).nth(1);
+        let has = |flag: &str| args().any(|arg| arg == flag);
+        if let Some(fps) = value("--fps") {
+            self.framerate = fps.parse().expect("--fps expects a number");
+        }
+        if let Some(clones) = value("--clones") {
+            self.max_clones = clones.parse().expect("--clones expects a number");
+        }
+        if let Some(size) = value("--size") {
+            let (width, height) = size.split_once('x').and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+                .expect("--size expects <width>x<height>");
+            self.width = width;
+            self.height = height;
+        }
+        self.turbo |= has("--turbo");
+        self.interpolation |= has("--interpolate");
+        self.hq_pen |= has("--hqpen");
+        self.fencing &= !has("--offscreen");
+        if has("--limitless") {
+            self.misc_limits = false;
+            self.max_clones = usize::MAX;
+        }
+        self
+    }
+
+    /// Time between ticks in RunMode::Scratch.
+    pub fn tick(&self) -> Duration {
+        let fps = if self.framerate == 0 { 60 } else { self.framerate as u64 };
+        Duration::from_nanos(1_000_000_000 / fps)
+    }
+
+    pub fn half_width(&self) -> f64 {
+        self.width as f64 / 2.0
+    }
+
+    pub fn half_height(&self) -> f64 {
+        self.height as f64 / 2.0
+    }
+}
@@ -1,6 +1,4 @@
-#![feature(trait_upcasting)]  // unfortunate that i need nightly
-
-use std::collections::{VecDeque};
+use std::collections::{HashMap, VecDeque};
 use std::fmt::Debug;
 use std::ops::Add;
 use std::time::{Duration};
@@ -12,10 +10,18 @@ pub use std::time::Instant;
 pub use instant::Instant;
 
 pub mod sprite;
+mod registry;
 pub mod builtins;
 pub mod callback;
 pub mod poly;
 pub mod backend;
+pub mod clock;
+pub mod config;
+mod replay;
+#[cfg(feature = "save")]
+pub mod save;
+#[cfg(feature = "cloud")]
+pub mod cloud;
 
 #[cfg(feature = "inspect")]
 pub mod ui;
@@ -25,22 +31,77 @@ pub use builtins::*;
 pub use poly::*;
 pub use backend::*;
 pub use callback::*;
+pub use config::Config;
+#[cfg(feature = "save")]
+pub use save::*;
+#[cfg(feature = "cloud")]
+use cloud::{CloudProvider, CloudSync};
+use replay::{Recorder, Replay};
 
 pub trait ScratchProgram<R: RenderBackend<Self>>: Sized + 'static {
-    type Msg: Debug + Copy + 'static;
+    type Msg: Debug + Copy + PartialEq + 'static;
     type Globals: Sprite<Self, R>;
 
+    #[allow(clippy::type_complexity)]
     fn create_initial_state() -> (Self::Globals, Vec<Box<dyn Sprite<Self, R>>>);
 
     // TODO: make this part of create_initial_state?
     fn get_costumes() -> Vec<ScratchAsset>;
 
-    // TODO: this is going to move to trait Sprite and the ctx method will accept only the resolved id. ImgId wrapper type?
-    fn costume_by_name(name: Str) -> Option<usize>;
+    /// For each of create_initial_state's sprites, where its costumes start in get_costumes and their names.
+    /// Names are only unique within a sprite (see costume_index).
+    fn costume_names() -> &'static [(usize, &'static [&'static str])];
 
     fn get_credits() -> &'static str;
+
+    /// Settings from the project. World applies the exe's flags on top (see Config::with_args).
+    fn config() -> Config;
+
+    /// The scripts with a hat for this trigger, top layer first.
+    /// Each is an index into create_initial_state's sprites and which of that sprite's scripts for trigger it is (see Sprite::receive_async).
+    /// Broadcasts only start these.
+    fn receivers(trigger: Trigger<Self::Msg>) -> &'static [(usize, usize)];
+
+    // The feature gated methods have defaults because another program in the same cargo workspace
+    // can turn on the runtime's feature for a program that was compiled without it.
+
+    /// Every message. Save states store them as an index into this.
+    #[cfg(feature = "save")]
+    fn all_msgs() -> &'static [Self::Msg] {
+        panic!("This program was built without the save feature")
+    }
+
+    /// Rebuilds a script from a save state. The id is whatever its Machine::save wrote first.
+    #[cfg(feature = "save")]
+    fn load_machine(_id: &str, _input: &mut SaveReader) -> IoAction<Self, R> {
+        panic!("This program was built without the save feature")
+    }
+
+    /// Indexes into the globals' vars (see Sprite::var) of the ones shared through a CloudProvider.
+    #[cfg(feature = "cloud")]
+    fn cloud_vars() -> &'static [usize] {
+        &[]
+    }
 }
 
+/// Called when a script finishes. The last one in its group lets the script waiting on them continue.
+fn leave_group(groups: &mut HashMap<usize, usize>, group: Option<usize>) {
+    if let Some(group) = group {
+        let left = groups.get_mut(&group).expect("Script finished twice");
+        *left -= 1;
+        if *left == 0 {
+            groups.remove(&group);
+        }
+    }
+}
+
+/// How many batches of polls poll_turbo does per frame in deterministic mode. About what fits in a frame in release mode.
+const DETERMINISTIC_BATCHES: usize = 100;
+/// How many passes over the scripts poll_scratch does per tick in deterministic mode.
+const DETERMINISTIC_PASSES: usize = 1000;
+/// A frame that comes a little early still runs the tick. Otherwise 60 fps backends would jitter down to 20.
+const TICK_SLACK: Duration = Duration::from_millis(2);
+
 /// Types for Msg and Globals are generated for a specific scratch program by the compiler.
 /// This crate needs to be generic over the user program but there will only ever be one instantiation of this generic in a given application.
 pub struct World<S: ScratchProgram<R>, R: RenderBackend<S>> {
@@ -48,13 +109,32 @@ pub struct World<S: ScratchProgram<R>, R: RenderBackend<S>> {
     custom: VecDeque<Box<dyn Sprite<S, R>>>,
     globals: S::Globals,
     scripts: Vec<Script<S, R>>,
+    /// How many scripts are still running in each group (see IoAction::ConcurrentScripts). Finished groups are removed.
+    groups: HashMap<usize, usize>,
+    next_group: usize,
     current_question: Option<String>,
     last_answer: Option<String>,
     pub mode: RunMode,
+    config: Config,
     events: VecDeque<SEvent>,
     pub futs_this_frame: usize, // Inspect Only. 
     pub none_futs_this_frame: usize, // Inspect Only. 
-
+    seed: Option<u64>,
+    /// Set by FrameCtx when a script changes something on screen. Ends the tick in RunMode::Scratch.
+    redraw_requested: bool,
+    /// When RunMode::Scratch runs its next tick.
+    next_tick: Option<Instant>,
+    /// Frames since the world was created. Recordings use it to timestamp events.
+    frame: u64,
+    recorder: Option<Recorder>,
+    replay: Option<Replay>,
+    /// From --save. Written by on_exit.
+    save_path: Option<String>,
+    /// From --load. Loaded at the start of the first frame since that's when there's a render handle for the pen layer.
+    load_path: Option<String>,
+    /// Only set if the program has cloud variables.
+    #[cfg(feature = "cloud")]
+    cloud: Option<CloudSync>,
 }
 
 // TODO: make the rendering backend generic over the async backend so you could drop in replace a real async runtime?
@@ -67,47 +147,185 @@ impl<S: ScratchProgram<R>, R: RenderBackend<S> + 'static> World<S, R> {
         if args().any(|arg| &arg == "--credits") {
             println!("{}", S::get_credits())
         }
-        World {
+        let config = S::config().with_args();
+        let mut world = World {
             bases: vec![SpriteBase::default(); custom.len()].into(),
             custom: custom.into(),
             globals,
             scripts: vec![],
+            groups: HashMap::new(),
+            next_group: 0,
             current_question: None,
             last_answer: None,
-            mode: RunMode::Turbo,  // TODO: pass default on cli for when not inspect
+            // A screenshot of the first frame wants the drawing finished.
+            mode: if config.turbo || args().any(|arg| arg == "--first-frame-only") { RunMode::Turbo } else { RunMode::Scratch },
+            config,
             events: Default::default(),
             futs_this_frame: 0,
             none_futs_this_frame: 0,
+            seed: None,
+            redraw_requested: false,
+            next_tick: None,
+            frame: 0,
+            recorder: None,
+            replay: None,
+            save_path: args().skip_while(|arg| arg != "--save").nth(1),
+            load_path: args().skip_while(|arg| arg != "--load").nth(1),
+            #[cfg(feature = "cloud")]
+            cloud: None,
+        };
+        #[cfg(not(feature = "save"))]
+        assert!(world.save_path.is_none() && world.load_path.is_none(), "This program was built without the save feature so --save and --load don't work");
+        #[cfg(feature = "cloud")]
+        if !S::cloud_vars().is_empty() {
+            world.set_cloud(cloud::provider_from_args());
+        }
+        if let Some(seed) = args().skip_while(|arg| arg != "--deterministic").nth(1) {
+            world.set_deterministic(seed.parse().expect("--deterministic expects a number to seed the rng"));
         }
+        if let Some(path) = args().skip_while(|arg| arg != "--replay").nth(1) {
+            let replay = Replay::load(&path);
+            if let Some(seed) = replay.seed {
+                world.set_deterministic(seed);
+            }
+            world.replay = Some(replay);
+        }
+        if let Some(path) = args().skip_while(|arg| arg != "--record").nth(1) {
+            world.recorder = Some(Recorder::create(&path, world.seed));
+        }
+        world
     }
 
+    /// The project's settings with the exe's flags applied.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Seeds the rng and switches to a virtual clock that moves a fixed step each frame (see clock.rs).
+    /// The generated exe does this when passed --deterministic <seed>.
+    pub fn set_deterministic(&mut self, seed: u64) {
+        self.seed = Some(seed);
+        seed_rand(seed);
+        clock::start_virtual();
+    }
+
+    /// Where cloud variables are shared. By default the --cloud-server or --cloud-file passed to the exe.
+    #[cfg(feature = "cloud")]
+    pub fn set_cloud(&mut self, provider: Box<dyn CloudProvider>) {
+        self.cloud = Some(CloudSync::new::<S, R>(provider, &self.globals));
+    }
+
+    /// Writes values other programs set since the last call. run_frame does this at the start of each frame.
+    #[cfg(feature = "cloud")]
+    pub fn cloud_receive(&mut self) {
+        if let Some(cloud) = &mut self.cloud {
+            cloud.receive::<S, R>(&mut self.globals);
+        }
+    }
+
+    /// Shares any cloud variables scripts changed. run_frame does this at the end of each frame.
+    #[cfg(feature = "cloud")]
+    pub fn cloud_send(&mut self) {
+        if let Some(cloud) = &mut self.cloud {
+            cloud.send::<S, R>(&self.globals);
+        }
+    }
+
+    /// Back to the initial variables, sprites and clock then starts the green flag scripts.
+    /// The exe's flags aren't read again so --record keeps writing the same file and cloud variables keep their shared values.
     pub fn restart(&mut self) {
-        let mode = self.mode;
-        *self = Self::new();
-        self.mode = mode;
+        let (globals, custom) = S::create_initial_state();
+        self.bases = vec![SpriteBase::default(); custom.len()].into();
+        self.custom = custom.into();
+        self.globals = globals;
+        self.scripts.clear();
+        self.groups.clear();
+        self.next_group = 0;
+        self.current_question = None;
+        self.last_answer = None;
+        self.redraw_requested = false;
+        self.next_tick = None;
+        // Restarting means from the green flag, not from the save state.
+        self.load_path = None;
+        #[cfg(feature = "cloud")]
+        if let Some(cloud) = &self.cloud {
+            cloud.restore::<S, R>(&mut self.globals);
+        }
+        if let Some(seed) = self.seed {
+            self.set_deterministic(seed);
+        }
         self.events.push_back(SEvent::UiClearPen);
         self.broadcast_toplevel_async(Trigger::FlagClicked);
     }
 
+    /// Backends call this when the program is about to close. Writes the save state if the exe was passed --save.
+    pub fn on_exit(&mut self, _render: &mut R::Handle<'_>) {
+        #[cfg(feature = "save")]
+        if let Some(path) = self.save_path.take() {
+            self.save_state(_render, &path);
+            eprintln!("Saved state at {path}");
+        }
+    }
+
     // TODO: remove
-    pub fn broadcast(&mut self, render: &mut R::Handle<'_>, msg: Trigger<S::Msg>) {
+    pub fn broadcast(&mut self, _render: &mut R::Handle<'_>, msg: Trigger<S::Msg>) {
         self.broadcast_toplevel_async(msg);
     }
 
-    // TODO: the compiler knows which messages each type wants to listen to.
-    //       it could generate a separate array for each and have no virtual calls or traversing everyone on each message
-    // TODO: there would also be a version for broadcast and wait that adds listeners to its personal stack.
+    /// Like scratch, scripts start from the top layer down.
     pub fn broadcast_toplevel_async(&mut self, msg: Trigger<S::Msg>) {
-        for (owner, c) in self.custom.iter().enumerate() {
-            let action = c.receive_async(msg);
-            self.scripts.push(Script {
-                next: vec![action],
-                owner,
-                trigger: msg,
-            });
+        let scripts = Self::receiver_scripts(&self.custom, msg);
+        self.start_scripts(scripts);
+    }
+
+    // TODO: once there are clones this needs every instance of each receiving sprite not just the original.
+    /// A script for each hat that listens to msg, top layer first.
+    fn receiver_scripts(custom: &VecDeque<Box<dyn Sprite<S, R>>>, msg: Trigger<S::Msg>) -> Vec<Script<S, R>> {
+        S::receivers(msg).iter().map(|&(owner, hat)| Self::hat_script(&*custom[owner], owner, msg, hat)).collect()
+    }
+
+    fn hat_script(custom: &dyn Sprite<S, R>, owner: usize, trigger: Trigger<S::Msg>, hat: usize) -> Script<S, R> {
+        Script {
+            next: vec![custom.receive_async(trigger, hat)],
+            owner,
+            trigger,
+            group: None,
+            hat: Some(hat),
+        }
+    }
+
+    /// Like Scratch, a script whose hat is already running replaces the old copy (in the same place) instead of running twice.
+    fn start_scripts(&mut self, scripts: Vec<Script<S, R>>) {
+        for script in scripts {
+            let running = match script.hat {
+                Some(_) => self.scripts.iter_mut().find(|s| s.hat == script.hat && s.owner == script.owner && s.trigger == script.trigger),
+                None => None,
+            };
+            match running {
+                Some(old) => {
+                    // Whoever was waiting on the old copy (broadcast and wait) carries on.
+                    leave_group(&mut self.groups, old.group);
+                    *old = script;
+                }
+                None => self.scripts.push(script),
+            }
         }
     }
 
+    /// The stop sign: ends every script and clears speech and the question. The window stays open so the flag can start it again.
+    // TODO: stop sounds once there are any.
+    pub fn stop_all(&mut self, render: &mut R::Handle<'_>) {
+        self.scripts.clear();
+        self.groups.clear();
+        self.current_question = None;
+        render.clear_speech();
+    }
+
+    /// True once every script has finished. Scripts waiting on a timer or a question are still running.
+    pub fn is_finished(&self) -> bool {
+        self.scripts.is_empty()
+    }
+
     // TODO: remove cause this is almost never what you want
     /// Hang the thread until all scripts are finished OR waiting on timers.
     /// This is kinda like "Run without screen refresh" (but for everything, not just some custom blocks) and skipping timers.
@@ -121,22 +339,59 @@ impl<S: ScratchProgram<R>, R: RenderBackend<S> + 'static> World<S, R> {
     pub fn run_frame(&mut self, render: &mut R::Handle<'_>) {
         self.futs_this_frame = 0;
         self.none_futs_this_frame = 0;
-        for e in self.events.drain(0..) {
+        #[cfg(feature = "save")]
+        if let Some(path) = self.load_path.take() {
+            self.load_state(render, &path);
+        }
+        #[cfg(feature = "cloud")]
+        self.cloud_receive();
+        if let Some(replay) = &mut self.replay {
+            // Live input is ignored while replaying.
+            self.events.clear();
+            self.events.extend(replay.take(self.frame));
+        }
+        while let Some(e) = self.events.pop_front() {
+            if let Some(recorder) = &mut self.recorder {
+                recorder.record(self.frame, &e);
+            }
             match e {
                 SEvent::UiClearPen => render.pen_clear(),
+                SEvent::Flag => {
+                    self.stop_all(render);
+                    self.broadcast_toplevel_async(Trigger::FlagClicked);
+                }
+                SEvent::Stop => self.stop_all(render),
+                SEvent::Restart => {
+                    self.stop_all(render);
+                    self.restart();
+                }
+                SEvent::Answer(answer) => {
+                    assert!(self.last_answer.is_none());
+                    self.last_answer = Some(answer);
+                    self.current_question = None;
+                }
                 SEvent::Click(owner) => {
-                    let trigger = Trigger::SpriteClicked;
-                    let action = self.custom[owner].receive_async(trigger);
-                    self.scripts.push(Script {
-                        next: vec![action],
-                        owner,
-                        trigger,
-                    });
+                    let scripts = S::receivers(Trigger::SpriteClicked).iter()
+                        .filter(|&&(sprite, _)| sprite == owner)
+                        .map(|&(_, hat)| Self::hat_script(&*self.custom[owner], owner, Trigger::SpriteClicked, hat))
+                        .collect();
+                    self.start_scripts(scripts);
                 }
             }
         }
 
         match self.mode {
+            RunMode::Scratch => {
+                // Scratch steps at the project's framerate however often the backend draws.
+                let now = clock::now();
+                let tick = self.config.tick();
+                if self.next_tick.is_none_or(|next| now + TICK_SLACK >= next) {
+                    let next = self.next_tick.unwrap_or(now) + tick;
+                    // Don't try to catch up after a slow frame.
+                    self.next_tick = Some(if next < now { now + tick } else { next });
+                    self.poll_scratch(render);
+                }
+            }
             RunMode::Turbo => self.poll_turbo(render),
             RunMode::Throttle => {
                 self.poll(render);
@@ -148,6 +403,29 @@ impl<S: ScratchProgram<R>, R: RenderBackend<S> + 'static> World<S, R> {
                 }
             }
         }
+        #[cfg(feature = "cloud")]
+        self.cloud_send();
+        self.frame += 1;
+        clock::end_frame();
+    }
+
+    /// One tick of scratch-vm's sequencer: every script runs until it yields, over and over,
+    /// until none made progress, one changed something on screen, or the tick's time is up.
+    pub fn poll_scratch(&mut self, render: &mut R::Handle<'_>) {
+        if self.scripts.is_empty() { return; }
+        self.redraw_requested = false;
+        // Scratch leaves a quarter of each tick for drawing.
+        let stop_time = Instant::now().add(self.config.tick() * 3 / 4);
+        let deterministic = clock::is_deterministic();
+        let mut passes = 0;
+        loop {
+            let progress = self.poll(render);
+            passes += 1;
+            let out_of_time = if deterministic { passes >= DETERMINISTIC_PASSES } else { Instant::now() > stop_time };
+            if !progress || self.redraw_requested || out_of_time {
+                break
+            }
+        }
     }
 
     // TODO: this is unfortunate: i imagine getting the time is slow as fuck. can i have like waker thingy in another thread?
@@ -158,6 +436,9 @@ impl<S: ScratchProgram<R>, R: RenderBackend<S> + 'static> World<S, R> {
         // TODO: tune this number. i guess need to leave some time in the frame for rendering?
         // TODO: I really have no sense of scale of how much work you're supposed to do in a frame
         let stop_time = Instant::now().add(Duration::from_millis(16));
+        // The virtual clock doesn't move during a frame so count polls instead.
+        let deterministic = clock::is_deterministic();
+        let mut batches = 0;
 
         loop {
             let mut progress = true;
@@ -168,7 +449,9 @@ impl<S: ScratchProgram<R>, R: RenderBackend<S> + 'static> World<S, R> {
                     break
                 }
             }
-            if (!progress) || (Instant::now() > stop_time) {
+            batches += 1;
+            let out_of_time = if deterministic { batches >= DETERMINISTIC_BATCHES } else { Instant::now() > stop_time };
+            if (!progress) || out_of_time {
                 break
             }
         }
@@ -185,11 +468,25 @@ impl<S: ScratchProgram<R>, R: RenderBackend<S> + 'static> World<S, R> {
         if self.scripts.is_empty() { return false; }  // fast path for sync or finished programs
         let mut made_progress = false;  // Set to true if every script was just waiting on io/timer
         let mut stop_all = false; // TODO: this is ugly.
+        // Owners that ran stop other scripts. The index (after retain_mut) of the script that asked is in spared so it isn't stopped too.
+        let mut stop_others = vec![];
+        let mut spared = vec![];
+        // How many scripts retain_mut kept so far.
+        let mut kept = 0;
+        // Scratch runs each script until it yields and yields at the end of every loop. Turbo is the other way around.
+        let scratch = self.mode == RunMode::Scratch;
+        // Started by ConcurrentScripts. They can't be pushed while iterating so they start on the next poll.
+        let mut spawned = vec![];
 
         self.scripts.retain_mut(|c| {
             if stop_all {
                 return false;
             }
+            // Already stopped by a script earlier in this poll. The one that asked was already visited so this isn't it.
+            if stop_others.contains(&c.owner) {
+                leave_group(&mut self.groups, c.group);
+                return false;
+            }
 
             // (break) to retain and yield the script until next poll.
             // (continue) when future resolved and want to pop the next immediately.
@@ -199,8 +496,12 @@ impl<S: ScratchProgram<R>, R: RenderBackend<S> + 'static> World<S, R> {
                 let custom: &mut dyn Sprite<S, R> = &mut *self.custom[c.owner];  // This is what needs trait_upcasting
                 let ctx = &mut FrameCtx {
                     sprite,
+                    owner: c.owner,
                     globals: &mut self.globals,
                     render,
+                    redraw: &mut self.redraw_requested,
+                    config: &self.config,
+                    mode: self.mode,
                 };
 
                 #[cfg(feature = "inspect")]
@@ -211,23 +512,16 @@ impl<S: ScratchProgram<R>, R: RenderBackend<S> + 'static> World<S, R> {
                 match current {  // Take the next thing from the stack of futures we're waiting on.
                     None => {  // If its empty, this script is finished.
                         made_progress = true;
+                        leave_group(&mut self.groups, c.group);
                         return /*from closure*/ false
                     },
                     Some(action) => match action {  // Poll the future.
-                        IoAction::StopCurrentScript => {
+                        IoAction::StopCurrentScript => unreachable!("Only a FutMachine returns StopCurrentScript and that's handled when it's resumed"),
+                        IoAction::StopOtherScripts => {
                             made_progress = true;
-                            loop {
-                                match c.next.pop() {
-                                    None => return false,
-                                    Some(a) => {
-                                        if matches!(a, IoAction::FutMachine(_, _, _)) {
-                                            break
-                                        } else {
-                                            // Continue
-                                        }
-                                    }
-                                }
-                            }
+                            stop_others.push(c.owner);
+                            spared.push(kept);
+                            // It carries on from the next poll.
                             break
                         }
 
@@ -247,7 +541,7 @@ impl<S: ScratchProgram<R>, R: RenderBackend<S> + 'static> World<S, R> {
                             if seconds > 0.0 {
                                 // TODO: what's scratch's timer resolution? Is there some value that should just decay to a yield?
                                 // Note: not from_seconds because dont want to round down to zero.
-                                let replace = IoAction::WaitUntil(Instant::now().add(Duration::from_millis((seconds * 1000.0) as u64)));
+                                let replace = IoAction::WaitUntil(clock::now().add(Duration::from_millis((seconds * 1000.0) as u64)));
                                 // made_progress = true;  We know we're about to be blocked.
                                 c.next.push(replace);
                                 break
@@ -257,7 +551,7 @@ impl<S: ScratchProgram<R>, R: RenderBackend<S> + 'static> World<S, R> {
                         }
                         // TODO: this doesnt work on web
                         IoAction::WaitUntil(time) => {
-                            let now = Instant::now();
+                            let now = clock::now();
                             if now <= time {  // Still waiting, no progress
                                 c.next.push(IoAction::WaitUntil(time));
                                 break
@@ -295,23 +589,22 @@ impl<S: ScratchProgram<R>, R: RenderBackend<S> + 'static> World<S, R> {
                         }
 
                         IoAction::BroadcastWait(msg) => {
-                            let mut s = vec![];
-                            for (owner, c) in self.custom.iter().enumerate() {
-                                let action = c.receive_async(Trigger::Message(msg));
-                                s.push(Script {
-                                    next: vec![action],
-                                    owner,
-                                    trigger: Trigger::Message(msg),
-                                });
-                            }
+                            let s = Self::receiver_scripts(&self.custom, Trigger::Message(msg));
                             c.next.push(IoAction::ConcurrentScripts(s));
                             made_progress = true;
                             break
                         },
                         IoAction::CloneMyself => todo!(),
                         IoAction::Concurrent(actions) => {
-                            // TODO: this is very wrong but works for now. if i dont guarantee execution order this isn't technically invalid.
-                            c.next.extend(actions.into_iter());
+                            // Each gets its own stack so one waiting doesn't block the others.
+                            let scripts = actions.into_iter().map(|action| Script {
+                                next: vec![action],
+                                owner: c.owner,
+                                trigger: c.trigger,
+                                group: None,
+                                hat: None,
+                            }).collect();
+                            c.next.push(IoAction::ConcurrentScripts(scripts));
                             made_progress = true;
                             continue
                         },
@@ -320,23 +613,43 @@ impl<S: ScratchProgram<R>, R: RenderBackend<S> + 'static> World<S, R> {
                             return false;
                         }
                         IoAction::ConcurrentScripts(scripts) => {
-                            assert_eq!(scripts.len(), 1, "temp debugging");
-                            for script in scripts {
-                                assert_eq!(script.owner, c.owner, "todo HACK");
-                                c.next.extend(script.next.into_iter());
+                            let group = self.next_group;
+                            self.next_group += 1;
+                            if !scripts.is_empty() {
+                                self.groups.insert(group, scripts.len());
                             }
+                            spawned.extend(scripts.into_iter().map(|script| Script { group: Some(group), ..script }));
+                            c.next.push(IoAction::WaitForScripts(group));
                             made_progress = true;
                             break
                         }
+                        IoAction::WaitForScripts(group) => {
+                            if self.groups.contains_key(&group) {  // Still waiting, no progress
+                                c.next.push(IoAction::WaitForScripts(group));
+                                break
+                            } else {
+                                made_progress = true;
+                                continue
+                            }
+                        }
                         IoAction::FutMachine(mut f, name, state) => {
                             // TODO: this doesnt push a CallMarker since that's redundant now. Instead, make StopCurrentScript just pop to a FutMachine. (done but not tested)
-                            let (action, state) = f(ctx, custom, state);
+                            let (action, state) = f.resume(ctx, custom, state);
+                            if matches!(action, IoAction::StopCurrentScript) {
+                                // Dropping the machine returns to whatever called it (or finishes the script). Its state doesn't matter.
+                                made_progress = true;
+                                continue
+                            }
                             if let Some(state) = state {  // c.next is a stack, so push continuation first
                                 c.next.push(IoAction::FutMachine(f, name, state));
                             }  // else, that function is finished.
                             c.next.push(action);
                             made_progress = true;
-                            break
+                            if scratch {
+                                continue  // Whatever it returned decides if it yields.
+                            } else {
+                                break
+                            }
                         }
                     }
                 }
@@ -345,20 +658,49 @@ impl<S: ScratchProgram<R>, R: RenderBackend<S> + 'static> World<S, R> {
                 { unreachable!("end of loop. use explicit continue cause im afraid of forgetting") }
             }
 
-            return /*from closure*/  true
+            kept += 1;
+            true
         });
 
         if stop_all {
-            self.scripts.clear();
+            self.stop_all(render);
             return false;
         }
+        if !stop_others.is_empty() {
+            // Whoever was waiting on a stopped script (broadcast and wait) carries on.
+            let groups = &mut self.groups;
+            let mut keep = |s: &Script<S, R>| {
+                let stop = stop_others.contains(&s.owner);
+                if stop {
+                    leave_group(groups, s.group);
+                }
+                !stop
+            };
+            // The one that asked keeps its place so it still runs in the same order as the others.
+            let mut index = 0;
+            self.scripts.retain(|s| {
+                index += 1;
+                spared.contains(&(index - 1)) || keep(s)
+            });
+            spawned.retain(&mut keep);
+        }
+        self.start_scripts(spawned);
 
         made_progress
     }
 }
 
+impl<S: ScratchProgram<R>, R: RenderBackend<S> + 'static> Default for World<S, R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub enum RunMode {
+    /// Copies scratch-vm: 30 ticks a second, loops yield, and a tick ends early once something on screen changes.
+    Scratch,
+    /// Runs as much as possible every frame and loops don't yield. The exe takes --turbo.
     Turbo,
     Throttle,
     Manual(bool),
@@ -415,7 +757,16 @@ pub fn args() -> impl Iterator<Item=String> {
     vec![].into_iter()
 }
 
+/// Input from outside the program. Everything goes through World::events so it can be recorded (see replay.rs).
+#[cfg_attr(not(feature = "inspect"), allow(dead_code))]  // Click, Flag, Stop and Restart are sent by the debugger
 enum SEvent {
     UiClearPen,
-    Click(usize)
+    Click(usize),
+    /// Stops everything then starts the green flag scripts again.
+    Flag,
+    Stop,
+    /// The debugger's Hard Reset button. See World::restart.
+    Restart,
+    /// Response to the current sensing_askandwait question.
+    Answer(String),
 }
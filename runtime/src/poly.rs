@@ -1,14 +1,14 @@
 //! Wrapper types with more Scratch-like semantics.
 
-use std::fmt::Debug;
+use std::fmt::{Debug, Formatter, Write};
 use std::mem::size_of;
 use std::ops::Index;
+use std::rc::Rc;
 
 // TODO: avoid this whenever possible
-// TODO: could probably make this smaller
-/// Cloning is only expensive if it holds a dynamically computed string.
+/// Cloning never allocates. Same size as a Str since the tag fits in Str's unused tag values.
 #[derive(Clone, Debug, Default)]
-pub enum Poly {  // TODO: there are enough bits to pack this better, especially if you hoist the Str tag. Should fit in 3 words instead of 5.
+pub enum Poly {
     Num(f64),
     Str(Str),
     Bool(bool),
@@ -16,11 +16,29 @@ pub enum Poly {  // TODO: there are enough bits to pack this better, especially
     Empty
 }
 
+/// Cloning never allocates.
 #[derive(Clone, Debug)]
 pub enum Str {
     Const(&'static str),
     Char(char),
-    Owned(String),  // TODO: Rc
+    /// Short computed strings (like formatted numbers) are stored in place.
+    Inline(InlineStr),
+    Owned(Rc<str>),
+}
+
+// Lists of these are memory bound so don't let them grow by accident.
+const _: () = assert!(size_of::<Str>() == 3 * size_of::<usize>());
+#[cfg(target_pointer_width = "64")]
+const _: () = assert!(size_of::<Poly>() == 3 * size_of::<usize>());
+
+/// Leaves room for Str's tag and the length.
+const INLINE_CAP: usize = 3 * size_of::<usize>() - 2;
+
+/// Utf8 bytes stored in place instead of on the heap.
+#[derive(Clone, Copy)]
+pub struct InlineStr {
+    len: u8,
+    bytes: [u8; INLINE_CAP],
 }
 
 /// 1-indexed Vec with silently failing operations.
@@ -46,7 +64,7 @@ impl Poly {
     #[must_use]
     pub fn as_str(&self) -> Str {
         match self {
-            Poly::Num(n) => Str::Owned(n.to_string()),  // TODO: this is a bit fishy
+            Poly::Num(n) => Str::from_num(*n),  // TODO: this is a bit fishy
             Poly::Str(s) => s.clone(),
             Poly::Empty => Str::Const(""),
             // TODO: optimisation pass that makes sure you're not doing this because you're comparing to a string literal
@@ -57,8 +75,9 @@ impl Poly {
     #[must_use]
     pub fn as_bool(&self) -> bool {
         match self {
-            Poly::Num(n) => todo!("Tried to convert {:?} to bool.", n),
-            Poly::Str(s) => todo!("Tried to convert {:?} to bool.", s),
+            // Same as scratch's Cast.toBoolean.
+            Poly::Num(n) => *n != 0.0 && !n.is_nan(),
+            Poly::Str(s) => !matches!(s.as_ref().to_lowercase().as_str(), "" | "0" | "false"),
             Poly::Empty => false,
             Poly::Bool(b) => *b,
         }
@@ -75,6 +94,15 @@ impl Poly {
 }
 
 impl Str {
+    /// Does not allocate unless its a very long number.
+    fn from_num(n: f64) -> Str {
+        let mut s = InlineStr::default();
+        match write!(s, "{n}") {
+            Ok(_) => Str::Inline(s),
+            Err(_) => Str::from(n.to_string()),
+        }
+    }
+
     // TODO: what to do on OOB?
     /// Does not allocate.
     pub fn get_index(&self, index: f64) -> Str {
@@ -87,31 +115,74 @@ impl Str {
                 panic!("Tried to get {index} in {self:?}")
             }
             // TODO: is scratch unicode aware? Could be faster if its just bytes.
-            Str::Const(s)  => {
-                s.chars().nth(index).unwrap()
-            }
-            Str::Owned(s) => {
-                s.chars().nth(index).unwrap()
-            }
+            _ => self.as_ref().chars().nth(index).unwrap(),
         };
-        Str::Char(c)
+        if c.is_ascii() {
+            Str::Char(c)
+        } else {
+            Str::Inline(InlineStr::new(c.encode_utf8(&mut [0; 4])).unwrap())
+        }
     }
 
     pub fn len(&self) -> f64 {
         (match self {
             Str::Const(s) => s.len(),
+            Str::Inline(s) => s.len as usize,
             Str::Owned(s) => s.len(),
             Str::Char(_) => 1,
         }) as f64
     }
 
     // TODO: its unfortunate that this doesnt take self by value. need to track ownership in the compiler.
-    #[must_use = "Allocates a new string, does not mutate the original."]
+    #[must_use = "Allocates a new string (unless its short), does not mutate the original."]
     pub fn join(&self, other: Str) -> Str {
+        let mut inline = InlineStr::default();
+        if inline.write_str(self.as_ref()).and_then(|_| inline.write_str(other.as_ref())).is_ok() {
+            return Str::Inline(inline);
+        }
         let mut s = String::with_capacity((self.len() + other.len()) as usize);
         s.push_str(self.as_ref());
         s.push_str(other.as_ref());
-        Str::Owned(s)
+        Str::from(s)
+    }
+}
+
+impl InlineStr {
+    fn new(s: &str) -> Option<InlineStr> {
+        let mut inline = InlineStr::default();
+        inline.write_str(s).ok()?;
+        Some(inline)
+    }
+
+    fn as_str(&self) -> &str {
+        // SAFETY: bytes are only written by write_str, which copies a whole &str.
+        unsafe { std::str::from_utf8_unchecked(&self.bytes[..self.len as usize]) }
+    }
+}
+
+impl Default for InlineStr {
+    fn default() -> Self {
+        InlineStr { len: 0, bytes: [0; INLINE_CAP] }
+    }
+}
+
+impl Write for InlineStr {
+    /// Fails if it doesn't fit (and leaves self unchanged).
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        let start = self.len as usize;
+        let end = start + s.len();
+        if end > INLINE_CAP {
+            return Err(std::fmt::Error);
+        }
+        self.bytes[start..end].copy_from_slice(s.as_bytes());
+        self.len = end as u8;
+        Ok(())
+    }
+}
+
+impl Debug for InlineStr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self.as_str(), f)
     }
 }
 
@@ -128,43 +199,66 @@ impl<T: Clone + Debug> List<T> {
         self.0.clear()
     }
 
-    pub fn remove(&mut self, index: f64) {
-        let index = index - 1.0;
-        if index >= 0.0 && index < self.len() {  // Rounding
-            self.0.remove(index as usize);
+    pub fn remove(&mut self, index: impl ListIndex) {
+        if let Some(index) = index.offset(self.0.len()) {
+            self.0.remove(index);
         }
     }
 
     // This can't use IndexMut because it has to fail silently.
-    pub fn replace(&mut self, index: f64, value: T) {
-        let index = index - 1.0;
-        if index >= 0.0 && index < self.len() {  // Rounding
-            self.0[index as usize] = value;
+    pub fn replace(&mut self, index: impl ListIndex, value: T) {
+        if let Some(index) = index.offset(self.0.len()) {
+            self.0[index] = value;
         }
     }
 
     // UNUSED thus far
-    pub fn insert(&mut self, index: f64, value: T) {
-        let index = index - 1.0;
-        if index >= 0.0 && index < self.len() + 1.0 { // Rounding, allow one off the end
-            self.0.insert(index as usize, value);
+    pub fn insert(&mut self, index: impl ListIndex, value: T) {
+        if let Some(index) = index.offset(self.0.len() + 1) {  // Allow one off the end
+            self.0.insert(index, value);
         }
     }
 
-    pub(crate) fn iter(&self) -> impl Iterator<Item=&T> {
+    pub fn iter(&self) -> impl Iterator<Item=&T> {
         self.0.iter()
     }
 }
 
-impl<T: Clone + Debug + ConstEmpty> Index<f64> for List<T> {
+impl<T: Clone + Debug + ConstEmpty, I: ListIndex> Index<I> for List<T> {
     type Output = T;
 
-    fn index(&self, index: f64) -> &Self::Output {
-        let index = index - 1.0;
-        if index >= 0.0 && index < self.len() {  // Rounding
-            &self.0[index as usize]
-        } else {  // Fail silently
-            T::EMPTY
+    fn index(&self, index: I) -> &Self::Output {
+        match index.offset(self.0.len()) {
+            Some(index) => &self.0[index],
+            None => T::EMPTY,  // Fail silently
+        }
+    }
+}
+
+/// A 1-indexed position in a List. Integer variables skip the float conversion.
+pub trait ListIndex: Copy {
+    /// The 0-indexed position if it's in 0..len
+    fn offset(self, len: usize) -> Option<usize>;
+}
+
+impl ListIndex for f64 {
+    fn offset(self, len: usize) -> Option<usize> {
+        let index = self - 1.0;
+        if index >= 0.0 && index < len as f64 {  // Rounding
+            Some(index as usize)
+        } else {
+            None
+        }
+    }
+}
+
+impl ListIndex for i64 {
+    fn offset(self, len: usize) -> Option<usize> {
+        let index = self.checked_sub(1)?;
+        if index >= 0 && (index as u64) < len as u64 {
+            Some(index as usize)
+        } else {
+            None
         }
     }
 }
@@ -218,6 +312,13 @@ impl From<&'static str> for Str {
     }
 }
 
+impl From<String> for Str {
+    /// Does not allocate again if its short.
+    fn from(value: String) -> Self {
+        InlineStr::new(&value).map_or_else(|| Str::Owned(value.into()), Str::Inline)
+    }
+}
+
 impl From<Poly> for Str {
     /// If it was already a string, does not reallocate. Numbers do allocate tho...
     fn from(value: Poly) -> Self {
@@ -238,11 +339,12 @@ impl AsRef<str> for Str {
     fn as_ref(&self) -> &str {
         match self {
             Str::Const(s) => s,
-            Str::Owned(s) => s.as_str(),
+            Str::Inline(s) => s.as_str(),
+            Str::Owned(s) => s,
             Str::Char(c) => {
                 assert!(size_of::<char>() <= size_of::<usize>()); // if the compiler cant figure out this is constant, there are larger problems in the world.
                 let mut i = *c as usize;  // if you're somehow on a 16 bit machine you deserve whatever you get
-                if i >= 128usize { // TOD0: non-ascii char->str is not supported yet
+                if i >= 128usize { // get_index uses Inline for non-ascii chars so this shouldn't happen.
                     i = 0;
                 }
 
@@ -258,8 +360,8 @@ impl From<Vec<f64>> for List<Poly> {
     }
 }
 
-impl From<Vec<Poly>> for List<Poly> {
-    fn from(value: Vec<Poly>) -> Self {
+impl<T: Clone + Debug> From<Vec<T>> for List<T> {
+    fn from(value: Vec<T>) -> Self {
         List(value)
     }
 }
@@ -267,7 +369,11 @@ impl From<Vec<Poly>> for List<Poly> {
 
 // TODO: include_bytes! and pack it
 pub fn str_to_poly_list(s: &str) -> List<Poly> {
-    List::<Poly>::from(s.split(',').map(|v| v.parse().unwrap()).collect::<Vec<f64>>())
+    List::<Poly>::from(str_to_num_list(s).0)
+}
+
+pub fn str_to_num_list(s: &str) -> List<f64> {
+    List(s.split(',').map(|v| v.parse().unwrap()).collect())
 }
 
 // str needs to be a reference so its not obvious how to convert a char into one without allocating.
@@ -281,10 +387,7 @@ impl PartialEq for Str {
                 Str::Char(b) => a == b,
                 _ => other.as_ref().len() == 1 && other.as_ref().chars().next().unwrap() == *a,
             },
-            _ => match other {
-                Str::Char(a) => return self.as_ref().len() == 1 && self.as_ref().chars().next().unwrap() == *a,
-                _ => {}
-            },
+            _ => if let Str::Char(a) = other { return self.as_ref().len() == 1 && self.as_ref().chars().next().unwrap() == *a },
         }
         self.as_ref() == other.as_ref()
     }
@@ -0,0 +1,32 @@
+//! The signatures of the FrameCtx methods the compiler calls directly for a block.
+//! runtime/src/builtins.rs checks the methods exist with these types
+//! and the compiler uses them to parse, type check and interpret.
+
+/// Passes the list of builtins to the macro named `$callback`. Types are SType variant names.
+#[macro_export]
+macro_rules! builtin_registry {
+    ($callback:ident) => {
+        $callback! {
+            pen_setPenColorToColor(colour: Number);
+            pen_setPenSizeTo(size: Number);
+            pen_penUp();
+            pen_penDown();
+            pen_stamp();
+            pen_clear();
+            motion_changexby(dx: Number);
+            motion_changeyby(dy: Number);
+            motion_setx(x: Number);
+            motion_sety(y: Number);
+            motion_gotoxy(x: Number, y: Number);
+            motion_xposition() -> Number;
+            motion_yposition() -> Number;
+            looks_hide();
+            looks_setsizeto(size: Number);
+            looks_switchcostumeto(costume: Str);
+            looks_switchcostumeto_id(costume: Integer);  // Only created by the compiler's optimizer.
+            looks_say(msg: Str);
+            sensing_answer() -> Str;
+            sensing_dayssince2000() -> Number;
+        }
+    };
+}
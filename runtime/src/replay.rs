@@ -0,0 +1,81 @@
+//! Records every input event with the frame it happened on so a run can be replayed exactly.
+//! The generated exe takes --record <path> or --replay <path>. Combine with --deterministic so random numbers and timers match too.
+//! Recordings of a deterministic run remember the seed and replaying them sets it.
+//!
+//! The file is one event per line: "{frame} {event} {args}". The first line might be "seed {n}".
+
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use crate::SEvent;
+
+pub(crate) struct Recorder {
+    out: BufWriter<File>,
+}
+
+impl Recorder {
+    pub(crate) fn create(path: &str, seed: Option<u64>) -> Recorder {
+        let mut out = BufWriter::new(File::create(path).unwrap_or_else(|e| panic!("Failed to create recording {path}: {e}")));
+        if let Some(seed) = seed {
+            writeln!(out, "seed {seed}").unwrap();
+        }
+        out.flush().unwrap();
+        Recorder { out }
+    }
+
+    /// Flushes every event so the recording is still useful if the program crashes.
+    pub(crate) fn record(&mut self, frame: u64, event: &SEvent) {
+        match event {
+            SEvent::UiClearPen => writeln!(self.out, "{frame} clear_pen"),
+            SEvent::Flag => writeln!(self.out, "{frame} flag"),
+            SEvent::Stop => writeln!(self.out, "{frame} stop"),
+            SEvent::Restart => writeln!(self.out, "{frame} restart"),
+            SEvent::Click(owner) => writeln!(self.out, "{frame} click {owner}"),
+            SEvent::Answer(text) => writeln!(self.out, "{frame} answer {}", text.replace('\n', " ")),
+        }.unwrap();
+        self.out.flush().unwrap();
+    }
+}
+
+pub(crate) struct Replay {
+    pub(crate) seed: Option<u64>,
+    events: VecDeque<(u64, SEvent)>,
+}
+
+impl Replay {
+    pub(crate) fn load(path: &str) -> Replay {
+        let src = fs::read_to_string(path).unwrap_or_else(|e| panic!("Failed to read recording {path}: {e}"));
+        let mut seed = None;
+        let mut events = VecDeque::new();
+        for (i, line) in src.lines().enumerate() {
+            let bad = || -> ! { panic!("Bad recording {path} line {}: {line:?}", i + 1) };
+            if let Some(n) = line.strip_prefix("seed ") {
+                seed = Some(n.parse().unwrap_or_else(|_| bad()));
+                continue;
+            }
+            let mut parts = line.splitn(3, ' ');
+            let frame: u64 = parts.next().and_then(|n| n.parse().ok()).unwrap_or_else(|| bad());
+            let event = match (parts.next(), parts.next()) {
+                (Some("clear_pen"), None) => SEvent::UiClearPen,
+                (Some("flag"), None) => SEvent::Flag,
+                (Some("stop"), None) => SEvent::Stop,
+                (Some("restart"), None) => SEvent::Restart,
+                (Some("click"), Some(owner)) => SEvent::Click(owner.parse().unwrap_or_else(|_| bad())),
+                (Some("answer"), text) => SEvent::Answer(text.unwrap_or("").to_string()),
+                _ => bad(),
+            };
+            events.push_back((frame, event));
+        }
+        Replay { seed, events }
+    }
+
+    /// The events recorded on this frame.
+    pub(crate) fn take(&mut self, frame: u64) -> impl Iterator<Item=SEvent> + '_ {
+        std::iter::from_fn(move || {
+            match self.events.front() {
+                Some((f, _)) if *f <= frame => self.events.pop_front().map(|(_, e)| e),
+                _ => None,
+            }
+        })
+    }
+}
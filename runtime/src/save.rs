@@ -0,0 +1,473 @@
+//! Save states: the whole World written to a file and loaded back later.
+//! Lets players quit and resume long simulations, and lets a bug be debugged by loading the state just before it.
+//! The generated exe takes --save <path> (written when the program closes) and --load <path>.
+//!
+//! Scripts are stored by the names the compiler gave their state machines (see Machine::save)
+//! so a save only loads in the same build of the program that wrote it.
+//! The format is binary with no field names. Loading reads everything in the same order saving wrote it.
+
+use std::fmt::Debug;
+use std::fs;
+use std::mem::size_of;
+use std::num::NonZeroU16;
+use std::ops::Add;
+use std::time::Duration;
+use crate::{builtins, clock, Argb, IoAction, List, Pen, Poly, RenderBackend, RenderHandle, Script, ScratchProgram, Sprite, SpriteBase, Str, Trigger, VarBorrow, VarBorrowMut, World};
+
+/// Changes whenever the layout does so old saves fail clearly instead of loading garbage.
+const MAGIC: &[u8] = b"hctarcs save 4\n";
+
+#[derive(Default)]
+pub struct SaveWriter {
+    bytes: Vec<u8>,
+}
+
+pub struct SaveReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl SaveWriter {
+    pub fn str(&mut self, s: &str) {
+        s.len().save(self);
+        self.bytes.extend_from_slice(s.as_bytes());
+    }
+}
+
+impl<'a> SaveReader<'a> {
+    fn take(&mut self, n: usize) -> &'a [u8] {
+        assert!(n <= self.bytes.len(), "Save state is truncated");
+        let (head, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        head
+    }
+
+    pub fn string(&mut self) -> String {
+        let len = usize::load(self);
+        String::from_utf8(self.take(len).to_vec()).expect("Save state has a bad string")
+    }
+}
+
+/// Values that can be written to a save state. The generated Machine::save calls this for each local.
+pub trait Save: Sized {
+    fn save(&self, out: &mut SaveWriter);
+    fn load(input: &mut SaveReader) -> Self;
+}
+
+macro_rules! save_le_bytes {
+    ($($t:ty),*) => {$(
+        impl Save for $t {
+            fn save(&self, out: &mut SaveWriter) {
+                out.bytes.extend_from_slice(&self.to_le_bytes());
+            }
+
+            fn load(input: &mut SaveReader) -> Self {
+                <$t>::from_le_bytes(input.take(size_of::<$t>()).try_into().unwrap())
+            }
+        }
+    )*};
+}
+
+save_le_bytes!(u8, u16, u32, u64, u128, i64, f64);
+
+// Always 8 bytes so saves work between 32 and 64 bit builds.
+impl Save for usize {
+    fn save(&self, out: &mut SaveWriter) {
+        (*self as u64).save(out);
+    }
+
+    fn load(input: &mut SaveReader) -> Self {
+        u64::load(input).try_into().expect("Save state number doesn't fit in usize")
+    }
+}
+
+impl Save for bool {
+    fn save(&self, out: &mut SaveWriter) {
+        (*self as u8).save(out);
+    }
+
+    fn load(input: &mut SaveReader) -> Self {
+        u8::load(input) != 0
+    }
+}
+
+impl<const N: usize> Save for [u8; N] {
+    fn save(&self, out: &mut SaveWriter) {
+        out.bytes.extend_from_slice(self);
+    }
+
+    fn load(input: &mut SaveReader) -> Self {
+        input.take(N).try_into().unwrap()
+    }
+}
+
+impl Save for String {
+    fn save(&self, out: &mut SaveWriter) {
+        out.str(self);
+    }
+
+    fn load(input: &mut SaveReader) -> Self {
+        input.string()
+    }
+}
+
+impl Save for Str {
+    fn save(&self, out: &mut SaveWriter) {
+        out.str(self.as_ref());
+    }
+
+    fn load(input: &mut SaveReader) -> Self {
+        Str::from(input.string())
+    }
+}
+
+impl Save for Poly {
+    fn save(&self, out: &mut SaveWriter) {
+        match self {
+            Poly::Num(n) => { 0u8.save(out); n.save(out); }
+            Poly::Str(s) => { 1u8.save(out); s.save(out); }
+            Poly::Bool(b) => { 2u8.save(out); b.save(out); }
+            Poly::Empty => 3u8.save(out),
+        }
+    }
+
+    fn load(input: &mut SaveReader) -> Self {
+        match u8::load(input) {
+            0 => Poly::Num(Save::load(input)),
+            1 => Poly::Str(Save::load(input)),
+            2 => Poly::Bool(Save::load(input)),
+            3 => Poly::Empty,
+            tag => panic!("Save state has a bad value (tag {tag})"),
+        }
+    }
+}
+
+impl<T: Save> Save for Vec<T> {
+    fn save(&self, out: &mut SaveWriter) {
+        self.len().save(out);
+        for item in self {
+            item.save(out);
+        }
+    }
+
+    fn load(input: &mut SaveReader) -> Self {
+        let len = usize::load(input);
+        (0..len).map(|_| T::load(input)).collect()
+    }
+}
+
+impl<T: Save + Clone + Debug> Save for List<T> {
+    fn save(&self, out: &mut SaveWriter) {
+        (self.len() as usize).save(out);
+        for item in self.iter() {
+            item.save(out);
+        }
+    }
+
+    fn load(input: &mut SaveReader) -> Self {
+        List::from(Vec::<T>::load(input))
+    }
+}
+
+impl<T: Save> Save for Option<T> {
+    fn save(&self, out: &mut SaveWriter) {
+        self.is_some().save(out);
+        if let Some(value) = self {
+            value.save(out);
+        }
+    }
+
+    fn load(input: &mut SaveReader) -> Self {
+        bool::load(input).then(|| T::load(input))
+    }
+}
+
+impl Save for Duration {
+    fn save(&self, out: &mut SaveWriter) {
+        self.as_secs().save(out);
+        self.subsec_nanos().save(out);
+    }
+
+    fn load(input: &mut SaveReader) -> Self {
+        Duration::new(Save::load(input), Save::load(input))
+    }
+}
+
+impl Save for SpriteBase {
+    fn save(&self, out: &mut SaveWriter) {
+        self._uid.save(out);
+        self.x.save(out);
+        self.y.save(out);
+        self.direction.save(out);
+        self.speed.save(out);
+        self.pen.size.save(out);
+        self.pen.active.save(out);
+        self.pen.colour.0.save(out);
+        self.last_answer.save(out);
+        self.costume.save(out);
+        self.size_frac.save(out);
+        self.hidden.save(out);
+    }
+
+    fn load(input: &mut SaveReader) -> Self {
+        SpriteBase {
+            _uid: Save::load(input),
+            x: Save::load(input),
+            y: Save::load(input),
+            direction: Save::load(input),
+            speed: Save::load(input),
+            pen: Pen {
+                size: Save::load(input),
+                active: Save::load(input),
+                colour: Argb(Save::load(input)),
+            },
+            last_answer: Save::load(input),
+            costume: Save::load(input),
+            size_frac: Save::load(input),
+            hidden: Save::load(input),
+        }
+    }
+}
+
+impl<S: ScratchProgram<R>, R: RenderBackend<S> + 'static> World<S, R> {
+    /// Writes everything needed to continue the program later: variables, sprites, running scripts, timers, random numbers and the pen layer.
+    pub fn save_state(&self, render: &mut R::Handle<'_>, path: &str) {
+        let out = &mut SaveWriter::default();
+        out.bytes.extend_from_slice(MAGIC);
+        self.frame.save(out);
+        // Saved as the time left like timers.
+        self.next_tick.map(|tick| tick.saturating_duration_since(clock::now())).save(out);
+        self.seed.save(out);
+        clock::elapsed().save(out);
+        let (seed, stream, word_pos) = builtins::rand_state();
+        seed.save(out);
+        stream.save(out);
+        word_pos.save(out);
+        self.current_question.save(out);
+        self.last_answer.save(out);
+
+        save_vars(out, &self.globals);
+        self.bases.len().save(out);
+        for (base, custom) in self.bases.iter().zip(self.custom.iter()) {
+            base.save(out);
+            save_vars(out, &**custom);
+        }
+
+        self.scripts.len().save(out);
+        for script in &self.scripts {
+            save_script(out, script);
+        }
+
+        render.pen_pixels().save(out);
+        fs::write(path, &out.bytes).unwrap_or_else(|e| panic!("Failed to write save state {path}: {e}"));
+    }
+
+    /// Replaces the whole world with a save state written by save_state.
+    pub fn load_state(&mut self, render: &mut R::Handle<'_>, path: &str) {
+        let bytes = fs::read(path).unwrap_or_else(|e| panic!("Failed to read save state {path}: {e}"));
+        let input = &mut SaveReader { bytes: &bytes };
+        assert!(input.bytes.starts_with(MAGIC), "{path} is not a save state or was made by a different version of hctarcs");
+        input.take(MAGIC.len());
+
+        self.frame = Save::load(input);
+        let next_tick: Option<Duration> = Save::load(input);
+        let seed: Option<u64> = Save::load(input);
+        let elapsed: Option<Duration> = Save::load(input);
+        if let Some(seed) = seed {
+            self.set_deterministic(seed);
+        }
+        if let Some(elapsed) = elapsed {
+            clock::set_elapsed(elapsed);
+        }
+        self.next_tick = next_tick.map(|left| clock::now().add(left));
+        builtins::set_rand_state((Save::load(input), Save::load(input), Save::load(input)));
+        self.current_question = Save::load(input);
+        self.last_answer = Save::load(input);
+
+        load_vars(input, &mut self.globals);
+        let sprites = usize::load(input);
+        assert_eq!(sprites, self.custom.len(), "Save state has a different number of sprites. It was made by a different version of the program.");
+        for (base, custom) in self.bases.iter_mut().zip(self.custom.iter_mut()) {
+            *base = Save::load(input);
+            load_vars(input, &mut **custom);
+        }
+
+        let scripts = usize::load(input);
+        self.scripts = (0..scripts).map(|_| load_script(input)).collect();
+        // Groups are just a count of their scripts so don't need to be saved.
+        self.groups.clear();
+        for group in self.scripts.iter().filter_map(|script| script.group) {
+            *self.groups.entry(group).or_default() += 1;
+            self.next_group = self.next_group.max(group + 1);
+        }
+
+        render.pen_clear();
+        if let Some(pen) = Option::<Vec<u8>>::load(input) {
+            render.set_pen_pixels(&pen);
+        }
+        assert!(input.bytes.is_empty(), "Save state has extra data at the end");
+        // Anything queued before the load happened in a different timeline.
+        self.events.clear();
+    }
+}
+
+/// Variables are checked by name so a save from a different version of the program fails instead of mixing up values.
+fn save_vars<S: ScratchProgram<R>, R: RenderBackend<S> + 'static>(out: &mut SaveWriter, sprite: &dyn Sprite<S, R>) {
+    let names = sprite.get_var_names();
+    names.len().save(out);
+    for (i, name) in names.iter().enumerate() {
+        out.str(name);
+        match sprite.var(i) {
+            VarBorrow::Num(n) => { 0u8.save(out); n.save(out); }
+            VarBorrow::Int(n) => { 1u8.save(out); n.save(out); }
+            VarBorrow::Bool(b) => { 2u8.save(out); b.save(out); }
+            VarBorrow::Str(s) => { 3u8.save(out); s.save(out); }
+            VarBorrow::Poly(p) => { 4u8.save(out); p.save(out); }
+            VarBorrow::List(l) => { 5u8.save(out); l.save(out); }
+            VarBorrow::NumList(l) => { 6u8.save(out); l.save(out); }
+            VarBorrow::StrList(l) => { 7u8.save(out); l.save(out); }
+            VarBorrow::Fail => 8u8.save(out),
+        }
+    }
+}
+
+fn load_vars<S: ScratchProgram<R>, R: RenderBackend<S> + 'static>(input: &mut SaveReader, sprite: &mut dyn Sprite<S, R>) {
+    let names = sprite.get_var_names();
+    let count = usize::load(input);
+    assert_eq!(count, names.len(), "Save state has different variables. It was made by a different version of the program.");
+    for (i, name) in names.iter().enumerate() {
+        let saved = input.string();
+        assert_eq!(&saved, name, "Save state has different variables. It was made by a different version of the program.");
+        match (u8::load(input), sprite.var_mut(i)) {
+            (0, VarBorrowMut::Num(n)) => *n = Save::load(input),
+            (1, VarBorrowMut::Int(n)) => *n = Save::load(input),
+            (2, VarBorrowMut::Bool(b)) => *b = Save::load(input),
+            (3, VarBorrowMut::Str(s)) => *s = Save::load(input),
+            (4, VarBorrowMut::Poly(p)) => *p = Save::load(input),
+            (5, VarBorrowMut::List(l)) => *l = Save::load(input),
+            (6, VarBorrowMut::NumList(l)) => *l = Save::load(input),
+            (7, VarBorrowMut::StrList(l)) => *l = Save::load(input),
+            (8, VarBorrowMut::Fail) => {}
+            _ => panic!("Variable {name} has a different type in the save state. It was made by a different version of the program."),
+        }
+    }
+}
+
+fn save_script<S: ScratchProgram<R>, R: RenderBackend<S> + 'static>(out: &mut SaveWriter, script: &Script<S, R>) {
+    script.owner.save(out);
+    save_trigger::<S, R>(out, script.trigger);
+    script.group.save(out);
+    script.hat.save(out);
+    script.next.len().save(out);
+    for action in &script.next {
+        save_action(out, action);
+    }
+}
+
+fn load_script<S: ScratchProgram<R>, R: RenderBackend<S> + 'static>(input: &mut SaveReader) -> Script<S, R> {
+    let owner = Save::load(input);
+    let trigger = load_trigger::<S, R>(input);
+    let group = Save::load(input);
+    let hat = Save::load(input);
+    let len = usize::load(input);
+    let next = (0..len).map(|_| load_action(input)).collect();
+    Script { next, owner, trigger, group, hat }
+}
+
+fn save_msg<S: ScratchProgram<R>, R: RenderBackend<S> + 'static>(out: &mut SaveWriter, msg: S::Msg) {
+    let index = S::all_msgs().iter().position(|m| *m == msg).expect("Message missing from all_msgs");
+    index.save(out);
+}
+
+fn load_msg<S: ScratchProgram<R>, R: RenderBackend<S> + 'static>(input: &mut SaveReader) -> S::Msg {
+    *S::all_msgs().get(usize::load(input)).expect("Save state has a bad message")
+}
+
+fn save_trigger<S: ScratchProgram<R>, R: RenderBackend<S> + 'static>(out: &mut SaveWriter, trigger: Trigger<S::Msg>) {
+    match trigger {
+        Trigger::UiClearPen => 0u8.save(out),
+        Trigger::FlagClicked => 1u8.save(out),
+        Trigger::SpriteClicked => 2u8.save(out),
+        Trigger::Message(msg) => { 3u8.save(out); save_msg::<S, R>(out, msg); }
+    }
+}
+
+fn load_trigger<S: ScratchProgram<R>, R: RenderBackend<S> + 'static>(input: &mut SaveReader) -> Trigger<S::Msg> {
+    match u8::load(input) {
+        0 => Trigger::UiClearPen,
+        1 => Trigger::FlagClicked,
+        2 => Trigger::SpriteClicked,
+        3 => Trigger::Message(load_msg::<S, R>(input)),
+        tag => panic!("Save state has a bad trigger (tag {tag})"),
+    }
+}
+
+fn save_action<S: ScratchProgram<R>, R: RenderBackend<S> + 'static>(out: &mut SaveWriter, action: &IoAction<S, R>) {
+    match action {
+        IoAction::SleepSecs(seconds) => { 0u8.save(out); seconds.save(out); }
+        // Timers are saved as the time left so they still work when loaded by a later run.
+        IoAction::WaitUntil(time) => { 1u8.save(out); time.checked_duration_since(clock::now()).unwrap_or_default().save(out); }
+        IoAction::Ask(question) => { 2u8.save(out); question.save(out); }
+        IoAction::WaitForAsk(id) => { 3u8.save(out); id.save(out); }
+        IoAction::BroadcastWait(msg) => { 4u8.save(out); save_msg::<S, R>(out, *msg); }
+        IoAction::CloneMyself => 5u8.save(out),
+        IoAction::LoopYield => 6u8.save(out),
+        IoAction::StopAllScripts => 7u8.save(out),
+        IoAction::StopCurrentScript => 8u8.save(out),
+        IoAction::None => 9u8.save(out),
+        IoAction::Concurrent(actions) => {
+            10u8.save(out);
+            actions.len().save(out);
+            for action in actions {
+                save_action(out, action);
+            }
+        }
+        IoAction::ConcurrentScripts(scripts) => {
+            11u8.save(out);
+            scripts.len().save(out);
+            for script in scripts {
+                save_script(out, script);
+            }
+        }
+        IoAction::FutMachine(machine, _, state) => {
+            12u8.save(out);
+            state.get().save(out);
+            machine.save(out);
+        }
+        IoAction::WaitForScripts(group) => { 13u8.save(out); group.save(out); }
+        IoAction::StopOtherScripts => 14u8.save(out),
+    }
+}
+
+fn load_action<S: ScratchProgram<R>, R: RenderBackend<S> + 'static>(input: &mut SaveReader) -> IoAction<S, R> {
+    match u8::load(input) {
+        0 => IoAction::SleepSecs(Save::load(input)),
+        1 => IoAction::WaitUntil(clock::now().add(Duration::load(input))),
+        2 => IoAction::Ask(Save::load(input)),
+        3 => IoAction::WaitForAsk(Save::load(input)),
+        4 => IoAction::BroadcastWait(load_msg::<S, R>(input)),
+        5 => IoAction::CloneMyself,
+        6 => IoAction::LoopYield,
+        7 => IoAction::StopAllScripts,
+        8 => IoAction::StopCurrentScript,
+        9 => IoAction::None,
+        10 => {
+            let len = usize::load(input);
+            IoAction::Concurrent((0..len).map(|_| load_action(input)).collect())
+        }
+        11 => {
+            let len = usize::load(input);
+            IoAction::ConcurrentScripts((0..len).map(|_| load_script(input)).collect())
+        }
+        12 => {
+            let state = NonZeroU16::new(u16::load(input)).expect("Save state has a bad script state");
+            let id = input.string();
+            match S::load_machine(&id, input) {
+                IoAction::FutMachine(machine, name, _) => IoAction::FutMachine(machine, name, state),
+                _ => unreachable!("load_machine returns a FutMachine"),
+            }
+        }
+        13 => IoAction::WaitForScripts(Save::load(input)),
+        14 => IoAction::StopOtherScripts,
+        tag => panic!("Save state has a bad action (tag {tag})"),
+    }
+}
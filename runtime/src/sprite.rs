@@ -1,10 +1,12 @@
 use std::any::Any;
 use std::fmt::Debug;
 use crate::backend::RenderBackend;
-use crate::{IoAction, Poly, ScratchProgram};
+use crate::{IoAction, ScratchProgram};
 
-#[cfg(feature = "inspect")]
-use crate::ui::{VarBorrow, VarBorrowMut};
+#[cfg(feature = "vars")]
+use std::fmt::{Display, Formatter};
+#[cfg(feature = "vars")]
+use crate::{List, Poly, Str};
 
 #[derive(Clone, Debug)]
 pub struct SpriteBase {
@@ -41,7 +43,7 @@ pub struct Pen {
 pub struct Argb(pub u32);
 
 /// Wraps the custom Msg defined by the program and adds some builtin ones that the runtime knows how to construct.
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Trigger<Msg> {
     UiClearPen,
     FlagClicked,
@@ -53,24 +55,26 @@ pub trait Sprite<S: ScratchProgram<R>, R: RenderBackend<S>>: Debug + Any {
     // TODO: this is not an async function; it returns an async function. that's a strange choice
     //       But that makes it easier to call because you dont need a Ctx so the world doesn't need to store messages to the next frame.
     // TODO: this is going to return FutMachine
-    fn receive_async(&self, _msg: Trigger<S::Msg>) -> IoAction<S, R>;
+    /// The action that starts this sprite's hat'th script for msg, counting in the order the project lists them.
+    /// The world only asks for the hats in ScratchProgram::receivers. The index is how it finds a running copy to restart (see Script::hat).
+    fn receive_async(&self, msg: Trigger<S::Msg>, hat: usize) -> IoAction<S, R>;
 
     // You can't just say Sprite extends Clone because that returns Self so its not object safe.
     // You can't just impl here and have where Self: Clone cause you can't call it on the trait object.
     fn clone_boxed(&self) -> Box<dyn Sprite<S, R>>;
 
-    #[cfg(feature = "inspect")]
+    #[cfg(feature = "vars")]
     fn get_var_names(&self) -> &'static [&'static str] {
         &[]
     }
 
-    #[cfg(feature = "inspect")]
-    fn var(&self, _: usize) -> VarBorrow {
+    #[cfg(feature = "vars")]
+    fn var(&self, _: usize) -> VarBorrow<'_> {
         VarBorrow::Fail
     }
 
-    #[cfg(feature = "inspect")]  // TODO: alas we're getting to the point of wanting a console scripting language.... if only someone had a nice ast
-    fn var_mut(&mut self, _: usize) -> VarBorrowMut {
+    #[cfg(feature = "vars")]  // TODO: alas we're getting to the point of wanting a console scripting language.... if only someone had a nice ast
+    fn var_mut(&mut self, _: usize) -> VarBorrowMut<'_> {
         VarBorrowMut::Fail
     }
 }
@@ -81,7 +85,7 @@ impl Default for SpriteBase {
             _uid: 0,
             x: 0.0,
             y: 0.0,
-            direction: 0.0,
+            direction: 90.0,
             speed: 0.0,
             pen: Default::default(),
             last_answer: "".to_string(),
@@ -90,4 +94,64 @@ impl Default for SpriteBase {
             hidden: false,
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(feature = "vars")]
+#[derive(Debug)]
+pub enum VarBorrow<'a> {
+    Num(&'a f64),  // TODO: these could be by value since its just a word but consistency is easier rn
+    Int(&'a i64),
+    Bool(&'a bool),
+    Str(&'a Str),
+    Poly(&'a Poly),
+    List(&'a List<Poly>),
+    NumList(&'a List<f64>),
+    StrList(&'a List<Str>),
+    Fail
+}
+
+#[cfg(feature = "vars")]
+#[derive(Debug)]
+pub enum VarBorrowMut<'a> {
+    Num(&'a mut f64),
+    Int(&'a mut i64),
+    Bool(&'a mut bool),
+    Str(&'a mut Str),
+    Poly(&'a mut Poly),
+    List(&'a mut List<Poly>),
+    NumList(&'a mut List<f64>),
+    StrList(&'a mut List<Str>),
+    Fail
+}
+
+/// Formats values the same whichever type the compiler picked to store them so traces can be compared.
+#[cfg(feature = "vars")]
+impl Display for VarBorrow<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        fn list<'a, T: Clone + Debug + 'a>(f: &mut Formatter<'_>, list: &'a List<T>, item: fn(&'a T) -> VarBorrow<'a>) -> std::fmt::Result {
+            write!(f, "[")?;
+            for (i, value) in list.iter().enumerate() {
+                if i != 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}", item(value))?;
+            }
+            write!(f, "]")
+        }
+
+        match self {
+            VarBorrow::Num(n) => write!(f, "{n}"),
+            VarBorrow::Int(n) => write!(f, "{n}"),
+            VarBorrow::Bool(b) => write!(f, "{b}"),
+            VarBorrow::Str(s) => write!(f, "{:?}", s.as_ref()),
+            VarBorrow::Poly(Poly::Num(n)) => write!(f, "{n}"),
+            VarBorrow::Poly(Poly::Str(s)) => write!(f, "{:?}", s.as_ref()),
+            VarBorrow::Poly(Poly::Bool(b)) => write!(f, "{b}"),
+            VarBorrow::Poly(Poly::Empty) => write!(f, "\"\""),
+            VarBorrow::List(l) => list(f, l, VarBorrow::Poly),
+            VarBorrow::NumList(l) => list(f, l, VarBorrow::Num),
+            VarBorrow::StrList(l) => list(f, l, VarBorrow::Str),
+            VarBorrow::Fail => write!(f, "?"),
+        }
+    }
+}
@@ -1,9 +1,14 @@
 use std::collections::VecDeque;
+use std::fmt::Debug;
 use std::marker::PhantomData;
-use crate::{HALF_SCREEN_HEIGHT, HALF_SCREEN_WIDTH, List, Poly, RenderBackend, RunMode, ScratchProgram, SEvent, Sprite, Str, Trigger, World};
-use egui::{Button, CollapsingHeader, Color32, Context, Direction, Grid, Layout, Rgba, Separator, Ui};
-use egui::plot::{Legend, Line, Plot, PlotBounds, PlotPoints, Points};
-use macroquad::prelude::GREEN;
+use crate::{List, RenderBackend, RunMode, ScratchProgram, SEvent, Sprite, Trigger, VarBorrow, World};
+// notan ships a newer egui than egui-macroquad so use whichever one the backend will hand us.
+#[cfg(feature = "render-notan")]
+use notan::egui;
+use egui::{Button, CollapsingHeader, Context, Direction, Grid, Layout, Rgba, Separator, Ui};
+// TODO: egui moved plots to the egui_plot crate after the version egui-macroquad uses.
+#[cfg(not(feature = "render-notan"))]
+use egui::{Color32, plot::{Legend, Line, Plot, PlotBounds, PlotPoints, Points}};
 use crate::backend::RenderHandle;
 
 #[derive(Default)]
@@ -33,22 +38,19 @@ impl<S: ScratchProgram<R>, R: RenderBackend<S> + 'static> Debugger<S, R> {
         if world.current_question.is_some() {
             egui::Window::new("Ask")
                 .hscroll(true).vscroll(true)
-                .default_pos((0.0, HALF_SCREEN_HEIGHT as f32))
+                .default_pos((0.0, world.config().half_height() as f32))
                 .show(egui_ctx, |ui| {
                     ui.label(world.current_question.as_ref().unwrap());
                     ui.add(egui::TextEdit::singleline(&mut self.answer));
                     if ui.button("Submit").clicked() {
-                        assert!(world.last_answer.is_none());
-                        world.last_answer = Some(self.answer.clone());
-                        self.answer = String::new();
-                        world.current_question = None;
+                        world.events.push_back(SEvent::Answer(std::mem::take(&mut self.answer)));
                     }
                 });
         }
 
         egui::Window::new("Variables")
             .vscroll(true).hscroll(true).default_open(false)
-            .default_pos(((HALF_SCREEN_WIDTH * 2.0) as f32 + 20.0, 20.0))
+            .default_pos((world.config().width as f32 + 20.0, 20.0))
             .show(egui_ctx, |ui| {
                 let sprites = world.bases.iter().zip(world.custom.iter()).enumerate();
                 ui.add_sized((700.0, 700.0), |ui: &mut Ui| {
@@ -60,15 +62,9 @@ impl<S: ScratchProgram<R>, R: RenderBackend<S> + 'static> Debugger<S, R> {
                                 .show(ui, |ui| {
                                     for (i, name) in world.globals.get_var_names().into_iter().enumerate() {
                                         match world.globals.var(i) { // TODO: copy-n-paste
-                                            VarBorrow::List(lst) => {
-                                                CollapsingHeader::new(format!("{name} = List({len})", len=lst.len())
-                                                ).show(ui, |ui| {
-                                                    for (i, value) in lst.iter().enumerate() {
-                                                        ui.label(format!("[{i}] {value:?}"));
-                                                        ui.end_row();
-                                                    }
-                                                });
-                                            }
+                                            VarBorrow::List(lst) => show_list(ui, name, lst),
+                                            VarBorrow::NumList(lst) => show_list(ui, name, lst),
+                                            VarBorrow::StrList(lst) => show_list(ui, name, lst),
                                             other => {
                                                 ui.label(format!("{name} = {:?}", other));
                                             }
@@ -91,15 +87,9 @@ impl<S: ScratchProgram<R>, R: RenderBackend<S> + 'static> Debugger<S, R> {
                                     ui.end_row();
                                     for (i, name) in user.get_var_names().into_iter().enumerate() {
                                         match user.var(i) {
-                                            VarBorrow::List(lst) => {
-                                                CollapsingHeader::new(format!("{name} = List({len})", len=lst.len())
-                                                ).show(ui, |ui| {
-                                                    for (i, value) in lst.iter().enumerate() {
-                                                        ui.label(format!("[{i}] {value:?}"));
-                                                        ui.end_row();
-                                                    }
-                                                });
-                                            }
+                                            VarBorrow::List(lst) => show_list(ui, name, lst),
+                                            VarBorrow::NumList(lst) => show_list(ui, name, lst),
+                                            VarBorrow::StrList(lst) => show_list(ui, name, lst),
                                             other => {
                                                 ui.label(format!("{name} = {:?}", other));
                                             }
@@ -118,27 +108,27 @@ impl<S: ScratchProgram<R>, R: RenderBackend<S> + 'static> Debugger<S, R> {
 
         egui::Window::new("Settings")
             .hscroll(true).vscroll(true).default_open(true)
-            .default_pos(((HALF_SCREEN_WIDTH * 2.0) as f32 + 20.0, 120.0))
+            .default_pos((world.config().width as f32 + 20.0, 120.0))
             .show(egui_ctx, |ui| {
                 ui.horizontal(|ui| {
                     if ui.button("Flag").clicked() {
-                        // TODO: is this supposed to stop old flag events
-                        world.broadcast_toplevel_async(Trigger::FlagClicked);
+                        world.events.push_back(SEvent::Flag);
                     }
 
                     if ui.button("Stop").clicked() {
-                        world.scripts.clear();
+                        world.events.push_back(SEvent::Stop);
                         world.mode = RunMode::Manual(false)
                     }
 
                     if ui.button("Hard Reset").clicked() {
-                        world.restart();
+                        world.events.push_back(SEvent::Restart);
                     }
                 });
 
                 ui.add_space(10.0);
 
                 ui.horizontal(|ui| {
+                    ui.radio_value(&mut world.mode, RunMode::Scratch, "Scratch");
                     ui.radio_value(&mut world.mode, RunMode::Turbo, "Turbo");
                     ui.radio_value(&mut world.mode, RunMode::Throttle, "Throttle");
                     ui.radio_value(&mut world.mode, RunMode::Manual(false), "Manual");
@@ -161,7 +151,7 @@ impl<S: ScratchProgram<R>, R: RenderBackend<S> + 'static> Debugger<S, R> {
 
         egui::Window::new("Callstack")
             .hscroll(true).vscroll(true).default_open(false)
-            .default_pos(((HALF_SCREEN_WIDTH * 2.0) as f32 + 20.0, 240.0))
+            .default_pos((world.config().width as f32 + 20.0, 240.0))
             .show(egui_ctx, |ui| {
                 ui.add_sized((400.0, 400.0), |ui: &mut Ui| {
                     Grid::new("Futures")
@@ -195,7 +185,7 @@ impl<S: ScratchProgram<R>, R: RenderBackend<S> + 'static> Debugger<S, R> {
 
         egui::Window::new("Credits")
             .hscroll(true).vscroll(true).default_open(false)
-            .default_pos(((HALF_SCREEN_WIDTH * 2.0) as f32 + 180.0, 20.0))
+            .default_pos((world.config().width as f32 + 180.0, 20.0))
             .show(egui_ctx, |ui| {
                 ui.label(S::get_credits());
             });
@@ -209,9 +199,19 @@ impl<S: ScratchProgram<R>, R: RenderBackend<S> + 'static> Debugger<S, R> {
         self.none_actions.pop_front();
         self.none_actions.push_back(world.none_futs_this_frame);
 
+        #[cfg(feature = "render-notan")]
+        egui::Window::new("Perf")
+            .hscroll(true).vscroll(true).default_open(false)
+            .default_pos((world.config().width as f32 + 20.0, 340.0))
+            .show(egui_ctx, |ui| {
+                ui.label(format!("Frame Time: {:.1}ms", dt * 1000.0));
+                ui.label(format!("Futures Resolved: {} ({} None)", world.futs_this_frame, world.none_futs_this_frame));
+            });
+
+        #[cfg(not(feature = "render-notan"))]
         egui::Window::new("Perf")
             .hscroll(true).vscroll(true).default_open(false)
-            .default_pos(((HALF_SCREEN_WIDTH * 2.0) as f32 + 20.0, 340.0))
+            .default_pos((world.config().width as f32 + 20.0, 340.0))
             .default_height(200.0)
             .show(egui_ctx, |ui| {
                 ui.vertical(|ui| {
@@ -261,24 +261,14 @@ impl<S: ScratchProgram<R>, R: RenderBackend<S> + 'static> Debugger<S, R> {
     }
 }
 
-#[derive(Debug)]
-pub enum VarBorrow<'a> {
-    Num(&'a f64),  // TODO: these could be by value since its just a word but consistency is easier rn
-    Bool(&'a bool),
-    Str(&'a Str),
-    Poly(&'a Poly),
-    List(&'a List<Poly>),
-    Fail
-}
-
-#[derive(Debug)]
-pub enum VarBorrowMut<'a> {
-    Num(&'a mut f64),
-    Bool(&'a mut bool),
-    Str(&'a mut Str),
-    Poly(&'a mut Poly),
-    List(&'a mut List<Poly>),
-    Fail
+fn show_list<T: Clone + Debug>(ui: &mut Ui, name: &str, lst: &List<T>) {
+    CollapsingHeader::new(format!("{name} = List({len})", len=lst.len())
+    ).show(ui, |ui| {
+        for (i, value) in lst.iter().enumerate() {
+            ui.label(format!("[{i}] {value:?}"));
+            ui.end_row();
+        }
+    });
 }
 
 pub struct DebugInfo {
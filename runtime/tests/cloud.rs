@@ -0,0 +1,73 @@
+#![cfg(feature = "cloud")]
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+use runtime::cloud::{serve_listener, CloudProvider, CloudSocket};
+
+/// Writing faster than the server reads fills the socket buffer. Those values have to arrive later instead of dropping the connection.
+#[test]
+fn send_when_socket_is_full() {
+    const COUNT: usize = 200;
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let (go, wait) = mpsc::channel();
+    let (done, received) = mpsc::channel();
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut lines = BufReader::new(stream).lines();
+        assert!(lines.next().unwrap().unwrap().contains("handshake"));
+        wait.recv().unwrap();
+        let sets = lines.map(Result::unwrap).filter(|line| line.contains("\"set\"")).take(COUNT).count();
+        done.send(sets).unwrap();
+    });
+
+    let mut socket = CloudSocket::connect(&addr);
+    let value = "9".repeat(100_000);
+    for _ in 0..COUNT {
+        socket.set("☁ big", &value);
+    }
+    go.send(()).unwrap();
+
+    let start = Instant::now();
+    loop {
+        socket.poll();
+        if let Ok(sets) = received.try_recv() {
+            assert_eq!(sets, COUNT);
+            break;
+        }
+        assert!(start.elapsed() < Duration::from_secs(10), "server never got every value");
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+/// A program that never reads fills its socket buffer. The server has to keep forwarding to everyone else.
+#[test]
+fn server_survives_stuck_client() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    thread::spawn(move || serve_listener(listener));
+
+    let mut stuck = TcpStream::connect(&addr).unwrap();
+    writeln!(stuck, "{{\"method\":\"handshake\"}}").unwrap();
+    let mut reader = CloudSocket::connect(&addr);
+    let mut writer = CloudSocket::connect(&addr);
+
+    let value = "9".repeat(100_000);
+    for i in 0..100 {
+        writer.set("☁ big", &format!("{value}{i}"));
+    }
+    writer.set("☁ done", "1");
+
+    let start = Instant::now();
+    loop {
+        writer.poll();
+        if reader.poll().iter().any(|(name, _)| name == "☁ done") {
+            break;
+        }
+        assert!(start.elapsed() < Duration::from_secs(10), "server stopped forwarding");
+        thread::sleep(Duration::from_millis(1));
+    }
+    drop(stuck);
+}
@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+use runtime::backend::pen::rasterize_line;
+use runtime::{Argb, Line};
+
+/// The coverage of every pixel that got plotted.
+fn draw(start: (f64, f64), end: (f64, f64), size: f64, canvas: (u32, u32)) -> HashMap<(u32, u32), f64> {
+    let mut pixels = HashMap::new();
+    rasterize_line(&Line { start, end, size, colour: Argb(0xFF000000) }, canvas, |x, y, coverage| {
+        assert!(pixels.insert((x, y), coverage).is_none(), "plotted ({x}, {y}) twice");
+    });
+    pixels
+}
+
+fn coverage(pixels: &HashMap<(u32, u32), f64>, x: u32, y: u32) -> f64 {
+    pixels.get(&(x, y)).copied().unwrap_or(0.0)
+}
+
+#[test]
+fn round_caps() {
+    // Screen space from (16, 20) to (24, 20) with radius 3.
+    let pixels = draw((-4.0, 0.0), (4.0, 0.0), 6.0, (40, 40));
+    // Past the end on the axis is inside the cap.
+    assert!(coverage(&pixels, 26, 19) > 0.9);
+    // The same distance from the line beside it is covered...
+    assert_eq!(coverage(&pixels, 20, 22), 1.0);
+    // ...but the corner of a square cap is not.
+    assert_eq!(coverage(&pixels, 26, 22), 0.0);
+    assert_eq!(coverage(&pixels, 13, 17), 0.0);
+}
+
+#[test]
+fn thin_lines_land_on_pixel_centers() {
+    for (size, rows) in [(1.0, 9..10), (3.0, 8..11)] {
+        let pixels = draw((-5.0, 0.0), (5.0, 0.0), size, (20, 20));
+        for y in 0..20 {
+            let expected = if rows.contains(&y) { 1.0 } else { 0.0 };
+            assert_eq!(coverage(&pixels, 10, y), expected, "size {size} row {y}");
+        }
+    }
+
+    // Other sizes aren't nudged so a 2px line covers the two rows either side of y = 0.
+    let pixels = draw((-5.0, 0.0), (5.0, 0.0), 2.0, (20, 20));
+    let rows: Vec<_> = (0..20).filter(|&y| coverage(&pixels, 10, y) > 0.0).collect();
+    assert_eq!(rows, [9, 10]);
+}
+
+#[test]
+fn zero_length_is_a_dot() {
+    let pixels = draw((0.0, 0.0), (0.0, 0.0), 4.0, (20, 20));
+    assert_eq!(coverage(&pixels, 9, 9), 1.0);
+    assert_eq!(coverage(&pixels, 10, 10), 1.0);
+    for (&(x, y), &c) in &pixels {
+        assert_eq!(c, coverage(&pixels, 19 - x, y), "not symmetric at ({x}, {y})");
+        assert_eq!(c, coverage(&pixels, x, 19 - y), "not symmetric at ({x}, {y})");
+    }
+    assert!(pixels.keys().all(|&(x, y)| (7..13).contains(&x) && (7..13).contains(&y)));
+
+    // Sizes below the minimum still draw something.
+    assert!(!draw((0.0, 0.0), (0.0, 0.0), 0.0, (20, 20)).is_empty());
+}
+
+#[test]
+fn clipped_to_canvas() {
+    let pixels = draw((-100.0, 0.0), (100.0, 0.0), 5.0, (20, 20));
+    assert!(pixels.keys().all(|&(x, y)| x < 20 && y < 20));
+    assert_eq!(coverage(&pixels, 0, 10), 1.0);
+    assert_eq!(coverage(&pixels, 19, 10), 1.0);
+
+    assert!(draw((-100.0, -100.0), (-50.0, -60.0), 5.0, (20, 20)).is_empty());
+    assert!(draw((100.0, 100.0), (150.0, 160.0), 5.0, (20, 20)).is_empty());
+}